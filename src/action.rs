@@ -0,0 +1,134 @@
+use mlua::Lua;
+
+use crate::layout::Layout;
+use crate::state::{BackendDataExt, Compostate};
+
+/// Something the config asked the compositor to do. Lua callbacks cannot borrow the compositor
+/// state, so the `composto` functions queue actions which are run once the callback returns.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Spawn(String),
+    Quit,
+    EnterMode(String),
+    ExitMode,
+    /// Switch to the xkb layout with the given index, or to the next one.
+    SwitchKeyboardLayout(Option<u32>),
+    ViewTags {
+        tags: u32,
+        output: Option<String>,
+    },
+    ToggleTags {
+        tags: u32,
+        output: Option<String>,
+    },
+    MoveToTags(u32),
+    ToggleWindowTags(u32),
+    /// Actions on a window by id, for hooks handed a window rather than acting on the focus.
+    FocusWindow(u64),
+    MoveWindowToTags {
+        window: u64,
+        tags: u32,
+    },
+    CloseWindow(u64),
+    ToggleFloating,
+    ToggleFullscreen,
+    ToggleMaximized,
+    SetLayout {
+        layout: Layout,
+        tags: Option<u32>,
+    },
+    IncMasterCount(i32),
+    IncMasterRatio(f64),
+    SetOutputScale {
+        output: String,
+        scale: f64,
+    },
+    Reload,
+}
+
+#[derive(Default)]
+pub struct ActionQueue(pub Vec<Action>);
+
+pub fn queue_action(lua: &Lua, action: Action) {
+    lua.app_data_mut::<ActionQueue>()
+        .expect("action queue is always set")
+        .0
+        .push(action);
+}
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    pub fn apply_action(&mut self, action: Action) {
+        match action {
+            Action::Spawn(cmd) => self.spawn(&cmd),
+            Action::Quit => self
+                .running
+                .store(false, std::sync::atomic::Ordering::SeqCst),
+            Action::EnterMode(mode) => {
+                self.keybindings.enter_mode(mode);
+                self.emit_keybinding_mode();
+            }
+            Action::ExitMode => {
+                self.keybindings.exit_mode();
+                self.emit_keybinding_mode();
+            }
+            Action::SwitchKeyboardLayout(index) => self.switch_keyboard_layout(index),
+            Action::ViewTags { tags, output } => self.view_tags(tags, output),
+            Action::ToggleTags { tags, output } => self.toggle_tags(tags, output),
+            Action::MoveToTags(tags) => self.move_to_tags(tags),
+            Action::ToggleWindowTags(tags) => self.toggle_window_tags(tags),
+            // the window may be gone by the time the action runs
+            Action::FocusWindow(id) => {
+                if let Some(window) = self.window_by_id(id) {
+                    self.raise_window(&window);
+                }
+            }
+            Action::MoveWindowToTags { window, tags } => {
+                if let Some(window) = self.window_by_id(window) {
+                    self.move_window_to_tags(&window, tags);
+                }
+            }
+            Action::CloseWindow(id) => {
+                if let Some(toplevel) = self.window_by_id(id).and_then(|w| w.0.toplevel().cloned())
+                {
+                    toplevel.send_close();
+                }
+            }
+            Action::ToggleFloating => self.toggle_floating(),
+            Action::ToggleFullscreen => self.toggle_fullscreen(),
+            Action::ToggleMaximized => self.toggle_maximized(),
+            Action::SetLayout { layout, tags } => self.set_layout(layout, tags),
+            Action::IncMasterCount(delta) => self.inc_master_count(delta),
+            Action::IncMasterRatio(delta) => self.inc_master_ratio(delta),
+            Action::SetOutputScale { output, scale } => {
+                // outputs that are not there yet get their scale when they show up
+                if let Some(output) = self.output_by_name(&output) {
+                    self.set_output_scale(&output, scale);
+                }
+            }
+            // failures are reported by the reload itself
+            Action::Reload => {
+                let _ = self.reload_config();
+            }
+        }
+    }
+
+    /// Run every action queued by lua since the last call.
+    pub fn apply_queued_actions(&mut self) {
+        loop {
+            let actions = std::mem::take(
+                &mut self
+                    .config
+                    .lua
+                    .app_data_mut::<ActionQueue>()
+                    .expect("action queue is always set")
+                    .0,
+            );
+            if actions.is_empty() {
+                break;
+            }
+            for action in actions {
+                self.apply_action(action);
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use mlua::Function;
+use smithay::input::Seat;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::utils::SERIAL_COUNTER;
+use smithay::wayland::xdg_activation::{XdgActivationToken, XdgActivationTokenData};
+
+use crate::state::{BackendDataExt, Compostate};
+use crate::windowdata::CompostoWindow;
+
+/// Tokens older than this cannot take the focus anymore.
+pub const ACTIVATION_TOKEN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What happens to a window asking to be activated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ActivationPolicy {
+    Focus,
+    Urgent,
+    Ignore,
+}
+
+impl ActivationPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "focus" => Some(Self::Focus),
+            "urgent" => Some(Self::Urgent),
+            "ignore" => Some(Self::Ignore),
+            _ => None,
+        }
+    }
+}
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    /// Whether a token was created in response to recent input on our seat, which is what
+    /// entitles it to move the focus.
+    fn token_is_valid(&self, data: &XdgActivationTokenData) -> bool {
+        if data.timestamp.elapsed() > ACTIVATION_TOKEN_TIMEOUT {
+            return false;
+        }
+        let Some((serial, wl_seat)) = &data.serial else {
+            return false;
+        };
+        let Some(seat) = Seat::<Self>::from_resource(wl_seat) else {
+            return false;
+        };
+        if seat != self.seat {
+            return false;
+        }
+        seat.get_keyboard()
+            .and_then(|keyboard| keyboard.last_enter())
+            .map_or(false, |last_enter| serial.is_no_older_than(&last_enter))
+    }
+
+    /// Ask the config what to do with an activation request, focusing valid requests and marking
+    /// the window urgent otherwise when it has no say.
+    fn activation_policy(&mut self, window: &CompostoWindow, valid: bool) -> ActivationPolicy {
+        let default = if valid {
+            ActivationPolicy::Focus
+        } else {
+            ActivationPolicy::Urgent
+        };
+        // the hook borrows the lua state, which the queued actions need back
+        let policy = {
+            let lua = &self.config.lua;
+            let hook = self.config.with_data(|data| {
+                data.activation_policy
+                    .as_ref()
+                    .and_then(|key| lua.registry_value::<Function>(key).ok())
+            });
+            let Some(hook) = hook else {
+                return default;
+            };
+
+            match hook.call::<_, Option<String>>((window.app_id(), valid)) {
+                Ok(Some(name)) => ActivationPolicy::from_name(&name).unwrap_or_else(|| {
+                    eprintln!("activation: unknown policy `{name}`");
+                    default
+                }),
+                Ok(None) => default,
+                Err(e) => {
+                    eprintln!("activation: error in policy hook: {e}");
+                    default
+                }
+            }
+        };
+        self.apply_queued_actions();
+        policy
+    }
+
+    pub fn activate(
+        &mut self,
+        token: XdgActivationToken,
+        data: XdgActivationTokenData,
+        surface: WlSurface,
+    ) {
+        let valid = self.token_is_valid(&data);
+        // a token can only be used once
+        self.xdg_activation_state.remove_token(&token);
+
+        let Some(window) = self.window_for_surface(&surface) else {
+            return;
+        };
+        match self.activation_policy(&window, valid) {
+            ActivationPolicy::Focus => self.raise_window(&window),
+            ActivationPolicy::Urgent => {
+                window.state().urgent = true;
+                self.update_decorations();
+            }
+            ActivationPolicy::Ignore => {}
+        }
+    }
+
+    /// Switch the output of `window` to its first tag when it is hidden, then focus it.
+    pub fn raise_window(&mut self, window: &CompostoWindow) {
+        if !self.is_window_visible(window) {
+            let tags = window.state().tags;
+            if let Some(output) = self.window_output(window) {
+                self.view_tags(1 << tags.trailing_zeros(), Some(output.name()));
+            }
+        }
+        self.focus_window(window, SERIAL_COUNTER.next_serial());
+    }
+}
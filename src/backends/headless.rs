@@ -0,0 +1,188 @@
+use smithay::backend::allocator::Fourcc;
+use smithay::backend::renderer::damage::OutputDamageTracker;
+use smithay::backend::renderer::pixman::{PixmanRenderBuffer, PixmanRenderer};
+use smithay::backend::renderer::{Bind, ImportMemWl, Offscreen};
+use smithay::output::{Mode, Output, PhysicalProperties, Subpixel};
+use smithay::reexports::calloop::timer::{TimeoutAction, Timer};
+use smithay::reexports::calloop::EventLoop;
+use smithay::reexports::wayland_protocols::wp::presentation_time::server::wp_presentation_feedback;
+use smithay::reexports::wayland_server::Display;
+use smithay::utils::{Physical, Size};
+
+use crate::config::Config;
+use crate::render::{refresh_interval, render_output};
+use crate::state::{BackendDataExt, Compostate, LoopData};
+
+pub const DEFAULT_SIZE: (i32, i32) = (1920, 1080);
+const REFRESH: i32 = 60_000;
+
+pub struct BackendDataHeadless {
+    renderer: PixmanRenderer,
+    damage_tracker: OutputDamageTracker,
+    output: Output,
+    age: usize,
+    /// Set when something changed since the last frame.
+    render_pending: bool,
+    /// Set from rendering a frame until its simulated vblank.
+    frame_pending: bool,
+}
+
+impl BackendDataExt for BackendDataHeadless {
+    fn seat_name(&self) -> Option<String> {
+        Some("headless".into())
+    }
+
+    fn change_vt(&mut self, _vt: i32) {
+        // there is no vt without a screen
+    }
+
+    fn schedule_render(&mut self) {
+        self.render_pending = true;
+    }
+}
+
+/// Parse a `WIDTHxHEIGHT` string, as given on the command line.
+pub fn parse_size(s: &str) -> Option<Size<i32, Physical>> {
+    let (w, h) = s.split_once('x')?;
+    let (w, h) = (w.parse::<i32>().ok()?, h.parse::<i32>().ok()?);
+    if w <= 0 || h <= 0 {
+        return None;
+    }
+    Some((w, h).into())
+}
+
+pub fn run_headless(config: Config, size: Size<i32, Physical>) -> anyhow::Result<()> {
+    let mut event_loop = EventLoop::try_new()?;
+    let display: Display<Compostate<BackendDataHeadless>> = Display::new()?;
+    let mut display_handle = display.handle();
+
+    let mut renderer = PixmanRenderer::new()?;
+    let buffer: PixmanRenderBuffer =
+        renderer.create_buffer(Fourcc::Argb8888, (size.w, size.h).into())?;
+    renderer.bind(buffer)?;
+
+    let mode = Mode {
+        size,
+        refresh: REFRESH,
+    };
+    let output = Output::new(
+        "HEADLESS-1".to_string(),
+        PhysicalProperties {
+            size: (0, 0).into(),
+            subpixel: Subpixel::Unknown,
+            make: "Smithay".into(),
+            model: "Headless".into(),
+        },
+    );
+
+    let _global = output.create_global::<Compostate<BackendDataHeadless>>(&display.handle());
+    output.change_current_state(Some(mode), None, None, Some((0, 0).into()));
+    output.set_preferred(mode);
+
+    let damage_tracker = OutputDamageTracker::from_output(&output);
+
+    let backend_data = BackendDataHeadless {
+        renderer,
+        damage_tracker,
+        output: output.clone(),
+        age: 0,
+        render_pending: true,
+        frame_pending: false,
+    };
+
+    let mut state = Compostate::new(display, event_loop.handle(), backend_data, config)?;
+    state
+        .shm_state
+        .update_formats(state.backend_data.renderer.shm_formats());
+
+    state.space.map_output(&output, (0, 0));
+    state.output_added(&output);
+
+    println!("headless: virtual output of size {}x{}", size.w, size.h);
+
+    while state.running.load(std::sync::atomic::Ordering::SeqCst) {
+        let mut calloop_data = LoopData {
+            state,
+            dh: display_handle.clone(),
+        };
+        let dispatch_result = event_loop.dispatch(None, &mut calloop_data);
+
+        LoopData {
+            state,
+            dh: display_handle,
+        } = calloop_data;
+
+        if dispatch_result.is_err() {
+            state
+                .running
+                .store(false, std::sync::atomic::Ordering::SeqCst);
+        } else {
+            state.refresh();
+            state.render();
+            display_handle.flush_clients().unwrap();
+        }
+    }
+
+    Ok(())
+}
+
+impl Compostate<BackendDataHeadless> {
+    fn render(&mut self) {
+        if !self.backend_data.render_pending || self.backend_data.frame_pending {
+            return;
+        }
+
+        let drag_icon = self.drag_icon();
+        let backend_data = &mut self.backend_data;
+        let render_res = render_output(
+            &backend_data.output,
+            &self.space,
+            drag_icon.as_ref(),
+            &mut backend_data.renderer,
+            backend_data.age,
+            &mut backend_data.damage_tracker,
+        );
+
+        let output = backend_data.output.clone();
+        let (damaged, states) = match render_res {
+            // the same buffer is rendered into every frame, so after the first frame its
+            // contents are always exactly one frame old
+            Ok(res) => {
+                backend_data.age = 1;
+                (res.damage.is_some(), res.states)
+            }
+            Err(e) => {
+                eprintln!("Rendering error {e}");
+                backend_data.age = 0;
+                return;
+            }
+        };
+        backend_data.render_pending = false;
+        if !damaged {
+            self.send_frames_after_refresh(&output);
+            return;
+        }
+
+        // there is no vblank without a display, a timer at the refresh rate of the virtual
+        // output stands in for it
+        let mut feedback = self.take_presentation_feedback(&output, &states);
+        let refresh = refresh_interval(&output);
+        let res =
+            self.loop_handle
+                .insert_source(Timer::from_duration(refresh), move |_, _, data| {
+                    data.state.backend_data.frame_pending = false;
+                    feedback.presented(
+                        data.state.clock.now(),
+                        refresh,
+                        0,
+                        wp_presentation_feedback::Kind::Vsync,
+                    );
+                    TimeoutAction::Drop
+                });
+        match res {
+            Ok(_) => self.backend_data.frame_pending = true,
+            Err(e) => eprintln!("headless: unable to insert the frame timer: {e}"),
+        }
+        self.send_frames(&output);
+    }
+}
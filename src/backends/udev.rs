@@ -1,3 +1,674 @@
-pub fn run_udev() -> anyhow::Result<()> {
-    unimplemented!("hello from udev")
+use std::collections::HashMap;
+use std::path::Path;
+
+use smithay::backend::allocator::gbm::{GbmAllocator, GbmBufferFlags, GbmDevice};
+use smithay::backend::allocator::Fourcc;
+use smithay::backend::drm::compositor::DrmCompositor;
+use smithay::backend::drm::{
+    DrmDevice, DrmDeviceFd, DrmEvent, DrmEventMetadata, DrmEventTime, DrmNode,
+};
+use smithay::backend::egl::{EGLContext, EGLDisplay};
+use smithay::backend::libinput::{LibinputInputBackend, LibinputSessionInterface};
+use smithay::backend::renderer::gles::GlesRenderer;
+use smithay::backend::renderer::{ImportDma, ImportMemWl};
+use smithay::backend::session::libseat::LibSeatSession;
+use smithay::backend::session::{Event as SessionEvent, Session};
+use smithay::backend::udev::{all_gpus, primary_gpu, UdevBackend, UdevEvent};
+use smithay::delegate_dmabuf;
+use smithay::desktop::utils::OutputPresentationFeedback;
+use smithay::output::{Mode, Output, PhysicalProperties, Subpixel};
+use smithay::reexports::calloop::{EventLoop, RegistrationToken};
+use smithay::reexports::drm::control::{connector, crtc, Device as ControlDevice, ModeTypeFlags};
+use smithay::reexports::input::Libinput;
+use smithay::reexports::rustix::fs::OFlags;
+use smithay::reexports::wayland_protocols::wp::presentation_time::server::wp_presentation_feedback;
+use smithay::reexports::wayland_server::backend::GlobalId;
+use smithay::reexports::wayland_server::Display;
+use smithay::utils::DeviceFd;
+use smithay::wayland::dmabuf::{
+    DmabufFeedbackBuilder, DmabufGlobal, DmabufHandler, DmabufState, ImportNotifier,
+};
+
+use crate::config::Config;
+use crate::cursor::cursor_elements;
+use crate::render::{output_elements, refresh_interval, CLEAR_COLOR};
+use crate::state::{BackendDataExt, Compostate, LoopData};
+
+const SUPPORTED_FORMATS: &[Fourcc] = &[
+    Fourcc::Abgr2101010,
+    Fourcc::Argb2101010,
+    Fourcc::Abgr8888,
+    Fourcc::Argb8888,
+];
+
+type GbmDrmCompositor = DrmCompositor<
+    GbmAllocator<DrmDeviceFd>,
+    GbmDevice<DrmDeviceFd>,
+    OutputPresentationFeedback,
+    DrmDeviceFd,
+>;
+
+pub struct BackendDataUdev {
+    session: LibSeatSession,
+    libinput: Libinput,
+    primary_gpu: DrmNode,
+    devices: HashMap<DrmNode, UdevDevice>,
+    dmabuf_state: DmabufState,
+    dmabuf_global: Option<DmabufGlobal>,
+}
+
+struct UdevDevice {
+    drm: DrmDevice,
+    gbm: GbmDevice<DrmDeviceFd>,
+    renderer: GlesRenderer,
+    surfaces: HashMap<crtc::Handle, UdevSurface>,
+    token: RegistrationToken,
+}
+
+struct UdevSurface {
+    connector: connector::Handle,
+    output: Output,
+    global: GlobalId,
+    compositor: GbmDrmCompositor,
+    /// Set while a frame has been queued and we are waiting for its vblank.
+    pending_frame: bool,
+    /// Set when something changed since the last frame, rendered once the vblank is in.
+    render_pending: bool,
+}
+
+impl BackendDataExt for BackendDataUdev {
+    fn seat_name(&self) -> Option<String> {
+        Some(self.session.seat())
+    }
+
+    fn change_vt(&mut self, vt: i32) {
+        if let Err(e) = self.session.change_vt(vt) {
+            eprintln!("udev: unable to switch to vt {vt}: {e}");
+        }
+    }
+
+    fn schedule_render(&mut self) {
+        for device in self.devices.values_mut() {
+            for surface in device.surfaces.values_mut() {
+                surface.render_pending = true;
+            }
+        }
+    }
+}
+
+impl DmabufHandler for Compostate<BackendDataUdev> {
+    fn dmabuf_state(&mut self) -> &mut DmabufState {
+        &mut self.backend_data.dmabuf_state
+    }
+
+    fn dmabuf_imported(
+        &mut self,
+        _global: &DmabufGlobal,
+        dmabuf: smithay::backend::allocator::dmabuf::Dmabuf,
+        notifier: ImportNotifier,
+    ) {
+        let primary_gpu = self.backend_data.primary_gpu;
+        let imported = self
+            .backend_data
+            .devices
+            .get_mut(&primary_gpu)
+            .map(|device| device.renderer.import_dmabuf(&dmabuf, None).is_ok())
+            .unwrap_or(false);
+
+        if imported {
+            let _ = notifier.successful::<Compostate<BackendDataUdev>>();
+        } else {
+            notifier.failed();
+        }
+    }
+}
+
+delegate_dmabuf!(Compostate<BackendDataUdev>);
+
+pub fn run_udev(config: Config) -> anyhow::Result<()> {
+    let mut event_loop = EventLoop::try_new()?;
+    let display: Display<Compostate<BackendDataUdev>> = Display::new()?;
+    let mut display_handle = display.handle();
+
+    let (session, session_notifier) = LibSeatSession::new()?;
+    let seat_name = session.seat();
+
+    let primary_gpu = primary_gpu(&seat_name)?
+        .and_then(|path| DrmNode::from_path(path).ok())
+        .or_else(|| {
+            all_gpus(&seat_name)
+                .ok()?
+                .into_iter()
+                .find_map(|path| DrmNode::from_path(path).ok())
+        })
+        .ok_or_else(|| anyhow::anyhow!("no gpu found on seat {seat_name}"))?;
+    println!("udev: using {primary_gpu} as the primary gpu");
+
+    let mut libinput =
+        Libinput::new_with_udev::<LibinputSessionInterface<LibSeatSession>>(session.clone().into());
+    libinput
+        .udev_assign_seat(&seat_name)
+        .map_err(|_| anyhow::anyhow!("unable to assign seat {seat_name} to libinput"))?;
+    let libinput_backend = LibinputInputBackend::new(libinput.clone());
+
+    let udev_backend = UdevBackend::new(&seat_name)?;
+
+    let backend_data = BackendDataUdev {
+        session,
+        libinput,
+        primary_gpu,
+        devices: HashMap::new(),
+        dmabuf_state: DmabufState::new(),
+        dmabuf_global: None,
+    };
+
+    let mut state = Compostate::new(display, event_loop.handle(), backend_data, config)?;
+
+    for (device_id, path) in udev_backend.device_list() {
+        if let Ok(node) = DrmNode::from_dev_id(device_id) {
+            if let Err(e) = state.device_added(node, path) {
+                eprintln!("udev: unable to add device {path:?}: {e}");
+            }
+        }
+    }
+
+    let primary_device = state
+        .backend_data
+        .devices
+        .get(&primary_gpu)
+        .ok_or_else(|| anyhow::anyhow!("unable to initialize the primary gpu"))?;
+    let shm_formats = primary_device.renderer.shm_formats();
+    let dmabuf_formats = primary_device.renderer.dmabuf_formats().collect::<Vec<_>>();
+    state.shm_state.update_formats(shm_formats);
+
+    let dmabuf_feedback =
+        DmabufFeedbackBuilder::new(primary_gpu.dev_id(), dmabuf_formats).build()?;
+    let dmabuf_global = state
+        .backend_data
+        .dmabuf_state
+        .create_global_with_default_feedback::<Compostate<BackendDataUdev>>(
+            &display_handle,
+            &dmabuf_feedback,
+        );
+    state.backend_data.dmabuf_global = Some(dmabuf_global);
+
+    event_loop
+        .handle()
+        .insert_source(udev_backend, |event, _, data| match event {
+            UdevEvent::Added { device_id, path } => {
+                if let Ok(node) = DrmNode::from_dev_id(device_id) {
+                    if let Err(e) = data.state.device_added(node, &path) {
+                        eprintln!("udev: unable to add device {path:?}: {e}");
+                    }
+                }
+            }
+            UdevEvent::Changed { device_id } => {
+                if let Ok(node) = DrmNode::from_dev_id(device_id) {
+                    data.state.device_changed(node);
+                }
+            }
+            UdevEvent::Removed { device_id } => {
+                if let Ok(node) = DrmNode::from_dev_id(device_id) {
+                    data.state.device_removed(node);
+                }
+            }
+        })
+        .expect("cant handle udev events");
+
+    event_loop
+        .handle()
+        .insert_source(libinput_backend, |event, _, data| {
+            data.state.process_input_event(event)
+        })
+        .expect("cant handle libinput events");
+
+    event_loop
+        .handle()
+        .insert_source(session_notifier, |event, _, data| match event {
+            SessionEvent::PauseSession => {
+                println!("udev: pausing session");
+                let backend_data = &mut data.state.backend_data;
+                backend_data.libinput.suspend();
+                for device in backend_data.devices.values_mut() {
+                    device.drm.pause();
+                }
+            }
+            SessionEvent::ActivateSession => {
+                println!("udev: resuming session");
+                let backend_data = &mut data.state.backend_data;
+                if backend_data.libinput.resume().is_err() {
+                    eprintln!("udev: failed to resume libinput");
+                }
+                let mut surfaces = Vec::new();
+                for (node, device) in backend_data.devices.iter_mut() {
+                    if let Err(e) = device.drm.activate(false) {
+                        eprintln!("udev: failed to activate {node}: {e}");
+                    }
+                    for (crtc, surface) in device.surfaces.iter_mut() {
+                        if let Err(e) = surface.compositor.reset_state() {
+                            eprintln!("udev: failed to reset drm surface state: {e}");
+                        }
+                        // the screen content is lost, draw everything again
+                        surface.pending_frame = false;
+                        surface.render_pending = true;
+                        surfaces.push((*node, *crtc));
+                    }
+                }
+                for (node, crtc) in surfaces {
+                    data.state.render_surface(node, crtc);
+                }
+            }
+        })
+        .expect("cant handle session events");
+
+    while state.running.load(std::sync::atomic::Ordering::SeqCst) {
+        let mut calloop_data = LoopData {
+            state,
+            dh: display_handle.clone(),
+        };
+        // commits, input and vblanks all wake us up, there is nothing to do in between
+        let dispatch_result = event_loop.dispatch(None, &mut calloop_data);
+
+        LoopData {
+            state,
+            dh: display_handle,
+        } = calloop_data;
+
+        if dispatch_result.is_err() {
+            state
+                .running
+                .store(false, std::sync::atomic::Ordering::SeqCst);
+        } else {
+            state.refresh();
+            state.render_scheduled();
+            display_handle.flush_clients().unwrap();
+        }
+    }
+
+    Ok(())
+}
+
+impl Compostate<BackendDataUdev> {
+    fn device_added(&mut self, node: DrmNode, path: &Path) -> anyhow::Result<()> {
+        let fd = self.backend_data.session.open(
+            path,
+            OFlags::RDWR | OFlags::CLOEXEC | OFlags::NOCTTY | OFlags::NONBLOCK,
+        )?;
+        let fd = DrmDeviceFd::new(DeviceFd::from(fd));
+
+        let (drm, notifier) = DrmDevice::new(fd.clone(), true)?;
+        let gbm = GbmDevice::new(fd)?;
+
+        let egl_display = unsafe { EGLDisplay::new(gbm.clone())? };
+        let egl_context = EGLContext::new(&egl_display)?;
+        let renderer = unsafe { GlesRenderer::new(egl_context)? };
+
+        let token = self
+            .loop_handle
+            .insert_source(notifier, move |event, metadata, data| match event {
+                DrmEvent::VBlank(crtc) => data.state.frame_finish(node, crtc, metadata),
+                DrmEvent::Error(e) => eprintln!("udev: drm error on {node}: {e}"),
+            })
+            .map_err(|e| e.error)?;
+
+        self.backend_data.devices.insert(
+            node,
+            UdevDevice {
+                drm,
+                gbm,
+                renderer,
+                surfaces: HashMap::new(),
+                token,
+            },
+        );
+
+        self.device_changed(node);
+        Ok(())
+    }
+
+    /// Rescan the connectors of a device, lighting up new ones and tearing down the ones that
+    /// went away.
+    fn device_changed(&mut self, node: DrmNode) {
+        let Some(device) = self.backend_data.devices.get_mut(&node) else {
+            return;
+        };
+        let resources = match device.drm.resource_handles() {
+            Ok(resources) => resources,
+            Err(e) => {
+                eprintln!("udev: unable to get resources of {node}: {e}");
+                return;
+            }
+        };
+
+        let mut connected = Vec::new();
+        let mut disconnected = Vec::new();
+        for handle in resources.connectors() {
+            let Ok(info) = device.drm.get_connector(*handle, true) else {
+                continue;
+            };
+            let known = device
+                .surfaces
+                .iter()
+                .find(|(_, surface)| surface.connector == *handle)
+                .map(|(crtc, _)| *crtc);
+
+            match (info.state(), known) {
+                (connector::State::Connected, None) => connected.push(info),
+                (connector::State::Connected, Some(_)) => {}
+                (_, Some(crtc)) => disconnected.push(crtc),
+                (_, None) => {}
+            }
+        }
+
+        for crtc in disconnected {
+            self.connector_disconnected(node, crtc);
+        }
+
+        for info in connected {
+            let Some(device) = self.backend_data.devices.get(&node) else {
+                return;
+            };
+            let crtc = info
+                .encoders()
+                .iter()
+                .filter_map(|handle| device.drm.get_encoder(*handle).ok())
+                .flat_map(|encoder| resources.filter_crtcs(encoder.possible_crtcs()))
+                .find(|crtc| !device.surfaces.contains_key(crtc));
+
+            match crtc {
+                Some(crtc) => self.connector_connected(node, info, crtc),
+                None => eprintln!(
+                    "udev: no free crtc for connector {}-{}",
+                    info.interface().as_str(),
+                    info.interface_id()
+                ),
+            }
+        }
+    }
+
+    fn device_removed(&mut self, node: DrmNode) {
+        let crtcs = match self.backend_data.devices.get(&node) {
+            Some(device) => device.surfaces.keys().copied().collect::<Vec<_>>(),
+            None => return,
+        };
+        for crtc in crtcs {
+            self.connector_disconnected(node, crtc);
+        }
+
+        if let Some(device) = self.backend_data.devices.remove(&node) {
+            self.loop_handle.remove(device.token);
+        }
+    }
+
+    fn connector_connected(&mut self, node: DrmNode, info: connector::Info, crtc: crtc::Handle) {
+        let Some(device) = self.backend_data.devices.get_mut(&node) else {
+            return;
+        };
+
+        let Some(drm_mode) = info
+            .modes()
+            .iter()
+            .find(|mode| mode.mode_type().contains(ModeTypeFlags::PREFERRED))
+            .or_else(|| info.modes().first())
+            .copied()
+        else {
+            return;
+        };
+
+        let name = format!("{}-{}", info.interface().as_str(), info.interface_id());
+        let surface = match device.drm.create_surface(crtc, drm_mode, &[info.handle()]) {
+            Ok(surface) => surface,
+            Err(e) => {
+                eprintln!("udev: unable to create a drm surface for {name}: {e}");
+                return;
+            }
+        };
+
+        let (w, h) = info.size().unwrap_or((0, 0));
+        let output = Output::new(
+            name.clone(),
+            PhysicalProperties {
+                size: (w as i32, h as i32).into(),
+                subpixel: Subpixel::Unknown,
+                make: "Unknown".into(),
+                model: "Unknown".into(),
+            },
+        );
+        let global = output.create_global::<Compostate<BackendDataUdev>>(&self.display_handle);
+
+        // outputs are laid out left to right in the order they are connected
+        let x = self
+            .space
+            .outputs()
+            .filter_map(|output| self.space.output_geometry(output))
+            .map(|geometry| geometry.loc.x + geometry.size.w)
+            .max()
+            .unwrap_or(0);
+
+        let mode = Mode::from(drm_mode);
+        output.change_current_state(Some(mode), None, None, Some((x, 0).into()));
+        output.set_preferred(mode);
+
+        let allocator = GbmAllocator::new(
+            device.gbm.clone(),
+            GbmBufferFlags::RENDERING | GbmBufferFlags::SCANOUT,
+        );
+        let render_formats = device
+            .renderer
+            .egl_context()
+            .dmabuf_render_formats()
+            .clone();
+
+        let compositor = match DrmCompositor::new(
+            &output,
+            surface,
+            None,
+            allocator,
+            device.gbm.clone(),
+            SUPPORTED_FORMATS,
+            render_formats,
+            device.drm.cursor_size(),
+            Some(device.gbm.clone()),
+        ) {
+            Ok(compositor) => compositor,
+            Err(e) => {
+                eprintln!("udev: unable to create a drm compositor for {name}: {e}");
+                self.display_handle
+                    .remove_global::<Compostate<BackendDataUdev>>(global);
+                return;
+            }
+        };
+
+        println!(
+            "udev: connected {name} at ({x}, 0), {}x{}@{}",
+            mode.size.w, mode.size.h, mode.refresh
+        );
+
+        device.surfaces.insert(
+            crtc,
+            UdevSurface {
+                connector: info.handle(),
+                output: output.clone(),
+                global,
+                compositor,
+                pending_frame: false,
+                render_pending: true,
+            },
+        );
+        self.space.map_output(&output, (x, 0));
+        self.output_added(&output);
+        self.arrange_all();
+
+        self.render_surface(node, crtc);
+    }
+
+    fn connector_disconnected(&mut self, node: DrmNode, crtc: crtc::Handle) {
+        let Some(device) = self.backend_data.devices.get_mut(&node) else {
+            return;
+        };
+        let Some(surface) = device.surfaces.remove(&crtc) else {
+            return;
+        };
+
+        println!("udev: disconnected {}", surface.output.name());
+
+        self.space.unmap_output(&surface.output);
+        self.update_visibility();
+        self.output_removed(&surface.output);
+        self.display_handle
+            .remove_global::<Compostate<BackendDataUdev>>(surface.global);
+    }
+
+    fn frame_finish(
+        &mut self,
+        node: DrmNode,
+        crtc: crtc::Handle,
+        metadata: &Option<DrmEventMetadata>,
+    ) {
+        let Some(surface) = self
+            .backend_data
+            .devices
+            .get_mut(&node)
+            .and_then(|device| device.surfaces.get_mut(&crtc))
+        else {
+            return;
+        };
+        surface.pending_frame = false;
+
+        let feedback = match surface.compositor.frame_submitted() {
+            Ok(feedback) => feedback,
+            Err(e) => {
+                eprintln!("udev: error while submitting a frame: {e}");
+                None
+            }
+        };
+        if let Some(mut feedback) = feedback {
+            // the kernel timestamps the flip on the monotonic clock unless told otherwise, in
+            // which case our own clock is the next best thing
+            let hw_time = metadata.as_ref().and_then(|metadata| match metadata.time {
+                DrmEventTime::Monotonic(time) => Some(time),
+                DrmEventTime::Realtime(_) => None,
+            });
+            let (time, flags) = match hw_time {
+                Some(time) => (
+                    time.into(),
+                    wp_presentation_feedback::Kind::Vsync
+                        | wp_presentation_feedback::Kind::HwClock
+                        | wp_presentation_feedback::Kind::HwCompletion,
+                ),
+                None => (self.clock.now(), wp_presentation_feedback::Kind::Vsync),
+            };
+            let sequence = metadata.as_ref().map_or(0, |metadata| metadata.sequence);
+            feedback.presented(
+                time,
+                refresh_interval(&surface.output),
+                sequence as u64,
+                flags,
+            );
+        }
+
+        self.render_surface(node, crtc);
+    }
+
+    /// Render every output that has changed and is not waiting for a vblank.
+    fn render_scheduled(&mut self) {
+        let scheduled = self
+            .backend_data
+            .devices
+            .iter()
+            .flat_map(|(node, device)| {
+                device
+                    .surfaces
+                    .iter()
+                    .filter(|(_, surface)| surface.render_pending && !surface.pending_frame)
+                    .map(|(crtc, _)| (*node, *crtc))
+            })
+            .collect::<Vec<_>>();
+        for (node, crtc) in scheduled {
+            self.render_surface(node, crtc);
+        }
+    }
+
+    fn render_surface(&mut self, node: DrmNode, crtc: crtc::Handle) {
+        if !self.backend_data.session.is_active() {
+            return;
+        }
+
+        let drag_icon = self.drag_icon();
+        let pointer_location = self
+            .seat
+            .get_pointer()
+            .map(|pointer| pointer.current_location());
+        let Some(device) = self.backend_data.devices.get_mut(&node) else {
+            return;
+        };
+        let Some(surface) = device.surfaces.get_mut(&crtc) else {
+            return;
+        };
+        if surface.pending_frame || !surface.render_pending {
+            return;
+        }
+        surface.render_pending = false;
+
+        // there is no cursor on a tty unless we draw one
+        let mut elements = match pointer_location {
+            Some(location) => cursor_elements(
+                &surface.output,
+                &self.space,
+                &self.cursor_status,
+                &self.default_cursor,
+                location,
+                &mut device.renderer,
+            ),
+            None => Vec::new(),
+        };
+        elements.extend(output_elements(
+            &surface.output,
+            &self.space,
+            drag_icon.as_ref(),
+            &mut device.renderer,
+        ));
+        let render_res =
+            surface
+                .compositor
+                .render_frame(&mut device.renderer, &elements, CLEAR_COLOR);
+        let output = surface.output.clone();
+        let states = match render_res {
+            Ok(res) if !res.is_empty => Some(res.states),
+            Ok(_) => None,
+            Err(e) => {
+                eprintln!("Rendering error {e}");
+                None
+            }
+        };
+
+        let queued = states.is_some_and(|states| {
+            let feedback = self.take_presentation_feedback(&output, &states);
+            let Some(surface) = self
+                .backend_data
+                .devices
+                .get_mut(&node)
+                .and_then(|device| device.surfaces.get_mut(&crtc))
+            else {
+                return false;
+            };
+            match surface.compositor.queue_frame(feedback) {
+                Ok(()) => {
+                    surface.pending_frame = true;
+                    true
+                }
+                Err(e) => {
+                    eprintln!("udev: unable to queue a frame: {e}");
+                    false
+                }
+            }
+        });
+
+        if queued {
+            self.send_frames(&output);
+        } else {
+            // nothing was submitted, so there will be no vblank to pace the clients
+            self.send_frames_after_refresh(&output);
+        }
+    }
 }
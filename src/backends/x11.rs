@@ -1,10 +1,9 @@
-use std::time::Duration;
-
 use smithay::delegate_dmabuf;
-use smithay::desktop::space::{render_output, space_render_elements};
+use smithay::desktop::utils::OutputPresentationFeedback;
 use smithay::output::{Mode, Output, PhysicalProperties, Subpixel};
 use smithay::reexports::ash::vk::ExtPhysicalDeviceDrmFn;
 use smithay::reexports::calloop::EventLoop;
+use smithay::reexports::wayland_protocols::wp::presentation_time::server::wp_presentation_feedback;
 use smithay::reexports::wayland_server::Display;
 use smithay::utils::{DeviceFd, Transform};
 use smithay::wayland::dmabuf::{
@@ -32,8 +31,10 @@ use smithay::{
     wayland::shm::with_buffer_contents_mut,
 };
 
+use crate::config::Config;
+use crate::ipc::Event;
+use crate::render::{refresh_interval, render_output};
 use crate::state::{BackendDataExt, Compostate, LoopData};
-use crate::windowdata::WindowRenderElement;
 
 pub struct BackendDataX11 {
     mode: Mode,
@@ -43,13 +44,27 @@ pub struct BackendDataX11 {
     dmabuf_state: DmabufState,
     _dmabuf_global: DmabufGlobal,
     _dmabuf_default_feedback: DmabufFeedback,
-    render: bool,
+    output: Output,
+    /// Set when something changed since the last frame.
+    render_pending: bool,
+    /// Set from submitting a frame until the X server reports it presented.
+    frame_pending: bool,
+    /// Presentation feedback of the frame waiting for `PresentCompleted`.
+    pending_feedback: Option<OutputPresentationFeedback>,
 }
 
 impl BackendDataExt for BackendDataX11 {
     fn seat_name(&self) -> Option<String> {
         None
     }
+
+    fn change_vt(&mut self, _vt: i32) {
+        // the x server switches vts itself
+    }
+
+    fn schedule_render(&mut self) {
+        self.render_pending = true;
+    }
 }
 
 /* impl BufferHandler for Compostate<BackendDataX11> {
@@ -86,7 +101,7 @@ impl DmabufHandler for Compostate<BackendDataX11> {
 
 delegate_dmabuf!(Compostate<BackendDataX11>);
 
-pub fn run_x11() -> anyhow::Result<()> {
+pub fn run_x11(config: Config) -> anyhow::Result<()> {
     let mut event_loop = EventLoop::try_new()?;
     let backend = X11Backend::new()?;
     let display: Display<Compostate<BackendDataX11>> = Display::new()?;
@@ -184,15 +199,19 @@ pub fn run_x11() -> anyhow::Result<()> {
         surface,
         _dmabuf_global: dmabuf_global,
         _dmabuf_default_feedback: dmabuf_feedback,
-        render: true,
+        output: output.clone(),
+        render_pending: true,
+        frame_pending: false,
+        pending_feedback: None,
     };
 
-    let mut state = Compostate::new(display, event_loop.handle(), backend_data)?;
+    let mut state = Compostate::new(display, event_loop.handle(), backend_data, config)?;
     state
         .shm_state
         .update_formats(state.backend_data.renderer.shm_formats());
 
     state.space.map_output(&output, (0, 0));
+    state.output_added(&output);
 
     // map output
     let output_clone = output.clone();
@@ -216,15 +235,29 @@ pub fn run_x11() -> anyhow::Result<()> {
                 output.delete_mode(output.current_mode().unwrap());
                 output.change_current_state(Some(data.state.backend_data.mode), None, None, None);
                 output.set_preferred(data.state.backend_data.mode);
+                data.state.arrange_all();
+                data.state.emit(Event::output_mode(output));
                 println!("x11: resized to new size: {new_size:#?}");
             }
-            X11Event::Refresh { .. } | X11Event::PresentCompleted { .. } => {
-                // println!("refresh event");
-                state.backend_data.render = true;
+            X11Event::Refresh { .. } => {
+                // the window was exposed, its old contents are gone
+                data.state.backend_data.surface.reset_buffers();
+                data.state.schedule_render();
             }
-            X11Event::Input(event) => {
-                // println!("X11 input event: {:#?}", event);
+            X11Event::PresentCompleted { .. } => {
+                let backend_data = &mut data.state.backend_data;
+                backend_data.frame_pending = false;
+                // the present extension tells us the frame is up but not when, so now it is
+                if let Some(mut feedback) = backend_data.pending_feedback.take() {
+                    feedback.presented(
+                        data.state.clock.now(),
+                        refresh_interval(&backend_data.output),
+                        0,
+                        wp_presentation_feedback::Kind::Vsync,
+                    );
+                }
             }
+            X11Event::Input(event) => data.state.process_input_event(event),
             X11Event::Focus(false) => {
                 println!("Lost focus");
             }
@@ -233,67 +266,12 @@ pub fn run_x11() -> anyhow::Result<()> {
         .expect("cant handle backend sources");
 
     while state.running.load(std::sync::atomic::Ordering::SeqCst) {
-        if state.backend_data.render {
-            // println!("do rendering here");
-            let backend_data = &mut state.backend_data;
-            backend_data.surface.reset_buffers();
-            let (buffer, dmabuf_age) = backend_data
-                .surface
-                .buffer()
-                .expect("cant get surface buffer");
-
-            if let Err(e) = backend_data.renderer.bind(buffer) {
-                eprintln!("error binding buffer: {e}");
-                continue;
-            }
-
-            /* let damage = Rectangle::from_loc_and_size((0, 0), size);
-            let mut frame = backend_data.renderer.render(size, Transform::Normal)?;
-            frame.clear([0.9, 0.9, 0.3, 1.0], &[damage])?;
-            let _ = frame.finish().unwrap();
-
-            println!("submitting surface");
-            backend_data.surface.submit()?; */
-
-            let output_clone = output.clone();
-
-            let spaces = vec![&state.space];
-            let custom_elems: Vec<WindowRenderElement<GlesRenderer>> = Vec::new();
-
-            let render_res = render_output(
-                &output_clone,
-                &mut backend_data.renderer,
-                1.0,
-                dmabuf_age.into(),
-                spaces,
-                &custom_elems,
-                &mut backend_data.damage_tracker,
-                [1.0, 1.0, 1.0, 1.0],
-            );
-
-            match render_res {
-                Ok(_) => {
-                    if let Err(e) = backend_data.surface.submit() {
-                        eprintln!("Error submiting surface buffers {e}");
-                        backend_data.surface.reset_buffers();
-                    } else {
-                        // backend_data.render = false;
-                        // std::thread::sleep(std::time::Duration::from_millis(16));
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Rendering error {e}");
-                    backend_data.surface.reset_buffers();
-                }
-            }
-        }
-
         let mut calloop_data = LoopData {
             state,
             dh: display_handle.clone(),
         };
-        let dispatch_result =
-            event_loop.dispatch(Some(Duration::from_millis(16)), &mut calloop_data);
+        // commits, input and presentation all wake us up, there is nothing to do in between
+        let dispatch_result = event_loop.dispatch(None, &mut calloop_data);
 
         LoopData {
             state,
@@ -305,9 +283,71 @@ pub fn run_x11() -> anyhow::Result<()> {
                 .running
                 .store(false, std::sync::atomic::Ordering::SeqCst);
         } else {
-            state.space.refresh();
+            state.refresh();
+            state.render();
             display_handle.flush_clients().unwrap();
         }
     }
     Ok(())
 }
+
+impl Compostate<BackendDataX11> {
+    fn render(&mut self) {
+        if !self.backend_data.render_pending || self.backend_data.frame_pending {
+            return;
+        }
+
+        let drag_icon = self.drag_icon();
+        let backend_data = &mut self.backend_data;
+        let (buffer, dmabuf_age) = match backend_data.surface.buffer() {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                eprintln!("error getting a surface buffer: {e}");
+                return;
+            }
+        };
+
+        if let Err(e) = backend_data.renderer.bind(buffer) {
+            eprintln!("error binding buffer: {e}");
+            return;
+        }
+
+        let render_res = render_output(
+            &backend_data.output,
+            &self.space,
+            drag_icon.as_ref(),
+            &mut backend_data.renderer,
+            dmabuf_age.into(),
+            &mut backend_data.damage_tracker,
+        );
+        backend_data.render_pending = false;
+
+        let output = backend_data.output.clone();
+        let (damaged, states) = match render_res {
+            Ok(res) => (res.damage.is_some(), res.states),
+            Err(e) => {
+                eprintln!("Rendering error {e}");
+                backend_data.surface.reset_buffers();
+                return;
+            }
+        };
+        if !damaged {
+            // nothing changed, the buffer goes back unsubmitted
+            self.send_frames_after_refresh(&output);
+            return;
+        }
+
+        let mut feedback = self.take_presentation_feedback(&output, &states);
+        let backend_data = &mut self.backend_data;
+        if let Err(e) = backend_data.surface.submit() {
+            eprintln!("Error submiting surface buffers {e}");
+            backend_data.surface.reset_buffers();
+            backend_data.render_pending = true;
+            feedback.discarded();
+        } else {
+            backend_data.frame_pending = true;
+            backend_data.pending_feedback = Some(feedback);
+            self.send_frames(&output);
+        }
+    }
+}
@@ -0,0 +1,664 @@
+use std::collections::HashMap;
+use std::os::fd::AsFd;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use mlua::{Function, Lua, RegistryKey, Table, Value, Variadic};
+use smithay::reexports::calloop::{
+    generic::Generic, Interest, LoopHandle, Mode, PostAction, RegistrationToken,
+};
+use smithay::reexports::rustix::fs::inotify::{
+    inotify_add_watch, inotify_init, CreateFlags, WatchFlags,
+};
+use smithay::reexports::rustix::io::read;
+
+use crate::action::{queue_action, Action, ActionQueue};
+use crate::decorations::{parse_decoration_mode, DecorationMode, DecorationStyle};
+use crate::floating::DEFAULT_SNAP_THRESHOLD;
+use crate::hooks::Hook;
+use crate::ipc::Event;
+use crate::keybindings::{
+    parse_sequence, Keybinding, Modifiers, DEFAULT_CHORD_TIMEOUT, DEFAULT_MODE,
+};
+use crate::keyboard::KeyboardConfig;
+use crate::layout::Layout;
+use crate::rules::WindowRule;
+use crate::scale::{MAX_SCALE, MIN_SCALE};
+use crate::state::{BackendDataExt, Compostate, LoopData};
+use crate::tags::MAX_TAGS;
+
+/// Configuration used when there is no user config, or when it fails to load.
+const DEFAULT_CONFIG: &str = include_str!("default.lua");
+
+/// Everything the config script registered through the `composto` global.
+pub struct ConfigData {
+    pub keybindings: Vec<Keybinding>,
+    pub chord_timeout: Duration,
+    pub keyboards: Vec<KeyboardConfig>,
+    pub tags: Vec<String>,
+    /// Layout functions defined in lua, by name.
+    pub layouts: HashMap<String, RegistryKey>,
+    /// Window rules, in the order they are applied.
+    pub rules: Vec<WindowRule>,
+    pub autostart: Vec<String>,
+    pub decorations: DecorationStyle,
+    /// Decoration modes forced on windows by app_id, whatever the client asks for.
+    pub decoration_modes: HashMap<String, DecorationMode>,
+    /// Decides what happens to windows asking to be activated.
+    pub activation_policy: Option<RegistryKey>,
+    /// Give the keyboard to whatever the pointer enters instead of what gets clicked.
+    pub focus_follows_mouse: bool,
+    /// Scale factors of outputs, by name.
+    pub output_scales: HashMap<String, f64>,
+    /// Reload the config whenever its file changes.
+    pub auto_reload: bool,
+    /// Handlers registered with `composto.on`, in registration order.
+    pub hooks: HashMap<Hook, Vec<RegistryKey>>,
+    /// Distance in pixels within which floating windows snap to edges while moved or resized.
+    pub snap_threshold: i32,
+    /// Modifiers to hold for moving and resizing windows by dragging them anywhere.
+    pub drag_modifier: Modifiers,
+    /// Set once the script ran to the end, from then on it is only called back into.
+    pub loaded: bool,
+}
+
+impl Default for ConfigData {
+    fn default() -> Self {
+        Self {
+            keybindings: Vec::new(),
+            chord_timeout: DEFAULT_CHORD_TIMEOUT,
+            keyboards: Vec::new(),
+            tags: (1..=9).map(|i| i.to_string()).collect(),
+            layouts: HashMap::new(),
+            rules: Vec::new(),
+            autostart: Vec::new(),
+            decorations: DecorationStyle::default(),
+            decoration_modes: HashMap::new(),
+            activation_policy: None,
+            focus_follows_mouse: false,
+            output_scales: HashMap::new(),
+            auto_reload: false,
+            hooks: HashMap::new(),
+            snap_threshold: DEFAULT_SNAP_THRESHOLD,
+            drag_modifier: Modifiers {
+                logo: true,
+                ..Default::default()
+            },
+            loaded: false,
+        }
+    }
+}
+
+pub struct Config {
+    pub lua: Lua,
+    pub path: Option<PathBuf>,
+}
+
+/// `$XDG_CONFIG_HOME/composto/init.lua`, falling back to `~/.config` when the variable is unset.
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_home.join("composto").join("init.lua"))
+}
+
+impl Config {
+    /// Load the config at `path`, or at the default location when no path is given. This never
+    /// fails: if the script cannot be read or errors out, the error is reported and the built-in
+    /// defaults are used instead.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        // a missing config is only worth reporting when it was asked for explicitly
+        let path = path.or_else(|| default_config_path().filter(|path| path.exists()));
+
+        if let Some(path) = path {
+            match Self::from_file(&path) {
+                Ok(config) => {
+                    println!("config: loaded {}", path.display());
+                    return config;
+                }
+                Err(e) => eprintln!("config: {e}"),
+            }
+        }
+
+        Self::defaults()
+    }
+
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let source = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("unable to read {}: {e}", path.display()))?;
+
+        let lua = Self::new_lua()?;
+        // the leading `@` makes lua report errors as `path:line: message`
+        lua.load(&source)
+            .set_name(format!("@{}", path.display()))
+            .exec()
+            .map_err(|e| anyhow::anyhow!("{e}"))?;
+        data_mut(&lua).loaded = true;
+
+        Ok(Self {
+            lua,
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    pub fn defaults() -> Self {
+        let lua = Self::new_lua().expect("unable to create the lua state");
+        lua.load(DEFAULT_CONFIG)
+            .set_name("=default config")
+            .exec()
+            .expect("the default config is broken");
+        data_mut(&lua).loaded = true;
+
+        Self { lua, path: None }
+    }
+
+    pub fn with_data<T>(&self, f: impl FnOnce(&ConfigData) -> T) -> T {
+        f(&self
+            .lua
+            .app_data_ref::<ConfigData>()
+            .expect("config data is always set"))
+    }
+
+    fn new_lua() -> mlua::Result<Lua> {
+        let lua = Lua::new();
+        lua.set_app_data(ConfigData::default());
+        lua.set_app_data(ActionQueue::default());
+
+        let composto = lua.create_table()?;
+
+        composto.set(
+            "bind",
+            lua.create_function(
+                |lua, (keys, callback, opts): (String, Function, Option<Table>)| {
+                    let sequence = parse_sequence(&keys).map_err(mlua::Error::RuntimeError)?;
+                    let (mode, on_release) = match opts {
+                        Some(opts) => (
+                            opts.get::<_, Option<String>>("mode")?
+                                .unwrap_or_else(|| DEFAULT_MODE.into()),
+                            opts.get::<_, Option<bool>>("on_release")?.unwrap_or(false),
+                        ),
+                        None => (DEFAULT_MODE.into(), false),
+                    };
+                    let callback = lua.create_registry_value(callback)?;
+                    data_mut(lua).keybindings.push(Keybinding {
+                        sequence,
+                        mode,
+                        on_release,
+                        callback,
+                    });
+                    Ok(())
+                },
+            )?,
+        )?;
+
+        composto.set(
+            "chord_timeout",
+            lua.create_function(|lua, ms: u64| {
+                data_mut(lua).chord_timeout = Duration::from_millis(ms);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "keyboard",
+            lua.create_function(|lua, opts: Table| {
+                let keyboard = KeyboardConfig::from_table(opts)?;
+                data_mut(lua).keyboards.push(keyboard);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "switch_keyboard_layout",
+            lua.create_function(|lua, index: Option<u32>| {
+                if index == Some(0) {
+                    return Err(mlua::Error::RuntimeError(
+                        "keyboard layouts are counted from 1".into(),
+                    ));
+                }
+                queue_action(lua, Action::SwitchKeyboardLayout(index.map(|i| i - 1)));
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "decorations",
+            lua.create_function(|lua, style: Table| {
+                data_mut(lua).decorations = DecorationStyle::from_table(style)?;
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "decoration_mode",
+            lua.create_function(|lua, (app_id, mode): (String, String)| {
+                let mode = parse_decoration_mode(&mode)?;
+                data_mut(lua).decoration_modes.insert(app_id, mode);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "activation_policy",
+            lua.create_function(|lua, hook: Function| {
+                let hook = lua.create_registry_value(hook)?;
+                data_mut(lua).activation_policy = Some(hook);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "focus_follows_mouse",
+            lua.create_function(|lua, enabled: bool| {
+                data_mut(lua).focus_follows_mouse = enabled;
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "output_scale",
+            lua.create_function(|lua, (output, scale): (String, f64)| {
+                if !(MIN_SCALE..=MAX_SCALE).contains(&scale) {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "output scale must be between {MIN_SCALE} and {MAX_SCALE}"
+                    )));
+                }
+                data_mut(lua).output_scales.insert(output.clone(), scale);
+                queue_action(lua, Action::SetOutputScale { output, scale });
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "reload",
+            lua.create_function(|lua, ()| {
+                // the reloaded config would reload itself again, forever
+                if !data_mut(lua).loaded {
+                    return Err(mlua::Error::RuntimeError(
+                        "the config cannot reload itself while it is loading".into(),
+                    ));
+                }
+                queue_action(lua, Action::Reload);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "auto_reload",
+            lua.create_function(|lua, enabled: bool| {
+                data_mut(lua).auto_reload = enabled;
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "enter_mode",
+            lua.create_function(|lua, mode: String| {
+                queue_action(lua, Action::EnterMode(mode));
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "exit_mode",
+            lua.create_function(|lua, ()| {
+                queue_action(lua, Action::ExitMode);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "spawn",
+            lua.create_function(|lua, cmd: String| {
+                queue_action(lua, Action::Spawn(cmd));
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "quit",
+            lua.create_function(|lua, ()| {
+                queue_action(lua, Action::Quit);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "tags",
+            lua.create_function(|lua, names: Variadic<String>| {
+                if names.is_empty() || names.len() > MAX_TAGS {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "there must be between 1 and {MAX_TAGS} tags"
+                    )));
+                }
+                data_mut(lua).tags = names.into_iter().collect();
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "view_tag",
+            lua.create_function(|lua, (tag, output): (Value, Option<String>)| {
+                let tags = tag_mask(lua, tag)?;
+                queue_action(lua, Action::ViewTags { tags, output });
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "toggle_tag",
+            lua.create_function(|lua, (tag, output): (Value, Option<String>)| {
+                let tags = tag_mask(lua, tag)?;
+                queue_action(lua, Action::ToggleTags { tags, output });
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "move_to_tag",
+            lua.create_function(|lua, tag: Value| {
+                let tags = tag_mask(lua, tag)?;
+                queue_action(lua, Action::MoveToTags(tags));
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "toggle_window_tag",
+            lua.create_function(|lua, tag: Value| {
+                let tags = tag_mask(lua, tag)?;
+                queue_action(lua, Action::ToggleWindowTags(tags));
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "layout",
+            lua.create_function(|lua, (name, tag): (String, Option<Value>)| {
+                let layout = Layout::from_name(&name)
+                    .or_else(|| {
+                        lua.app_data_ref::<ConfigData>()
+                            .expect("config data is always set")
+                            .layouts
+                            .contains_key(&name)
+                            .then(|| Layout::Lua(name.clone()))
+                    })
+                    .ok_or_else(|| mlua::Error::RuntimeError(format!("unknown layout `{name}`")))?;
+                let tags = tag.map(|tag| tag_mask(lua, tag)).transpose()?;
+                queue_action(lua, Action::SetLayout { layout, tags });
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "define_layout",
+            lua.create_function(|lua, (name, function): (String, Function)| {
+                if Layout::from_name(&name).is_some() {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "`{name}` is a built-in layout"
+                    )));
+                }
+                let function = lua.create_registry_value(function)?;
+                data_mut(lua).layouts.insert(name, function);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "inc_master_count",
+            lua.create_function(|lua, delta: i32| {
+                queue_action(lua, Action::IncMasterCount(delta));
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "inc_master_ratio",
+            lua.create_function(|lua, delta: f64| {
+                queue_action(lua, Action::IncMasterRatio(delta));
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "toggle_floating",
+            lua.create_function(|lua, ()| {
+                queue_action(lua, Action::ToggleFloating);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "toggle_fullscreen",
+            lua.create_function(|lua, ()| {
+                queue_action(lua, Action::ToggleFullscreen);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "toggle_maximize",
+            lua.create_function(|lua, ()| {
+                queue_action(lua, Action::ToggleMaximized);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "snap_threshold",
+            lua.create_function(|lua, pixels: i32| {
+                data_mut(lua).snap_threshold = pixels.max(0);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "drag_modifier",
+            lua.create_function(|lua, modifiers: String| {
+                data_mut(lua).drag_modifier =
+                    Modifiers::parse(&modifiers).map_err(mlua::Error::RuntimeError)?;
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "rule",
+            lua.create_function(|lua, rule: Table| {
+                let rule = WindowRule::from_table(lua, rule)?;
+                data_mut(lua).rules.push(rule);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "on",
+            lua.create_function(|lua, (name, handler): (String, Function)| {
+                let hook = Hook::from_name(&name)
+                    .ok_or_else(|| mlua::Error::RuntimeError(format!("unknown event `{name}`")))?;
+                let handler = lua.create_registry_value(handler)?;
+                data_mut(lua).hooks.entry(hook).or_default().push(handler);
+                Ok(())
+            })?,
+        )?;
+
+        composto.set(
+            "autostart",
+            lua.create_function(|lua, cmd: String| {
+                data_mut(lua).autostart.push(cmd);
+                Ok(())
+            })?,
+        )?;
+
+        lua.globals().set("composto", composto)?;
+
+        // scripts are expected to `print` for debugging, make it clear where the output comes from
+        lua.globals().set(
+            "print",
+            lua.create_function(|_, values: Variadic<Value>| {
+                let line = values
+                    .iter()
+                    .map(|value| value.to_string().unwrap_or_else(|_| "?".into()))
+                    .collect::<Vec<_>>()
+                    .join("\t");
+                println!("lua: {line}");
+                Ok(())
+            })?,
+        )?;
+
+        Ok(lua)
+    }
+}
+
+/// Resolve a tag given either by its 1-based index or by its name to its bit in a tag mask.
+pub fn tag_mask(lua: &Lua, tag: Value) -> mlua::Result<u32> {
+    let index = match tag {
+        Value::Integer(i) if i >= 1 && i as usize <= MAX_TAGS => i as usize - 1,
+        Value::String(name) => {
+            let name = name.to_str()?;
+            lua.app_data_ref::<ConfigData>()
+                .expect("config data is always set")
+                .tags
+                .iter()
+                .position(|tag| tag == name)
+                .ok_or_else(|| mlua::Error::RuntimeError(format!("unknown tag `{name}`")))?
+        }
+        other => {
+            return Err(mlua::Error::RuntimeError(format!(
+                "invalid tag `{}`",
+                other.to_string().unwrap_or_default()
+            )))
+        }
+    };
+    Ok(1 << index)
+}
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    /// Run the config again from scratch. The running config is only replaced once the new one
+    /// loaded without errors, so a typo never leaves the compositor without bindings. Either way
+    /// the outcome is reported to the subscribers of config events.
+    pub fn reload_config(&mut self) -> anyhow::Result<()> {
+        let path = self
+            .config
+            .path
+            .clone()
+            .or_else(|| default_config_path().filter(|path| path.exists()));
+        let config = match path {
+            Some(path) => Config::from_file(&path),
+            None => Ok(Config::defaults()),
+        };
+        let config = match config {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("config: reload failed, keeping the running config: {e}");
+                self.emit(Event::ConfigError {
+                    message: e.to_string(),
+                });
+                return Err(e);
+            }
+        };
+        self.config = config;
+        println!("config: reloaded");
+        self.emit(Event::ConfigReloaded);
+
+        // tags keep their layout unless the new config no longer defines it
+        for tag_layout in &mut self.tag_layouts {
+            if let Layout::Lua(name) = &tag_layout.layout {
+                if self
+                    .config
+                    .with_data(|data| !data.layouts.contains_key(name))
+                {
+                    tag_layout.layout = Layout::MasterStack;
+                }
+            }
+        }
+
+        self.reapply_window_rules();
+        self.keybindings.reset();
+        self.emit_keybinding_mode();
+        self.apply_keyboard_config();
+        for output in self.space.outputs().cloned().collect::<Vec<_>>() {
+            self.apply_output_scale(&output);
+        }
+        self.update_decorations();
+        self.arrange_all();
+        self.update_config_watcher();
+        // whatever the new config asked for while it was loading
+        self.apply_queued_actions();
+        Ok(())
+    }
+
+    /// Watch the directory of the config file when auto-reload is on, and stop watching it
+    /// otherwise. Editors often save by replacing the file, which a watch on the file itself
+    /// would not survive.
+    pub fn update_config_watcher(&mut self) {
+        let enabled = self.config.with_data(|data| data.auto_reload);
+        if !enabled {
+            // this may run from the watcher itself, which cannot go away while it is dispatched
+            if let Some(token) = self.config_watcher.take() {
+                self.loop_handle
+                    .insert_idle(move |data| data.state.loop_handle.remove(token));
+            }
+            return;
+        }
+        if self.config_watcher.is_some() {
+            return;
+        }
+        let Some(path) = self.config.path.clone() else {
+            eprintln!("config: running on the default config, there is nothing to watch");
+            return;
+        };
+
+        match watch_config(&self.loop_handle, path) {
+            Ok(token) => self.config_watcher = Some(token),
+            Err(e) => eprintln!("config: unable to watch the config for changes: {e}"),
+        }
+    }
+}
+
+fn watch_config<BackendData: BackendDataExt + 'static>(
+    lh: &LoopHandle<'static, LoopData<BackendData>>,
+    path: PathBuf,
+) -> anyhow::Result<RegistrationToken> {
+    let dir = path
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("{} has no parent directory", path.display()))?;
+    let fd = inotify_init(CreateFlags::CLOEXEC | CreateFlags::NONBLOCK)?;
+    inotify_add_watch(
+        fd.as_fd(),
+        dir,
+        WatchFlags::CLOSE_WRITE | WatchFlags::MOVED_TO | WatchFlags::CREATE,
+    )?;
+
+    let modified = |path: &Path| {
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    };
+    let mut last_modified = modified(&path);
+
+    lh.insert_source(
+        Generic::new(fd, Interest::READ, Mode::Level),
+        move |_, fd, data| {
+            // the events only say something in the directory changed, the modification time of
+            // the config tells whether it was the config
+            let mut buffer = [0; 4096];
+            while matches!(read(fd.as_ref(), &mut buffer), Ok(n) if n > 0) {}
+
+            let now_modified = modified(&path);
+            if now_modified.is_some() && now_modified != last_modified {
+                last_modified = now_modified;
+                println!("config: {} changed, reloading", path.display());
+                // failures are reported by the reload itself
+                let _ = data.state.reload_config();
+            }
+            Ok(PostAction::Continue)
+        },
+    )
+    .map_err(|e| anyhow::anyhow!("{}", e.error))
+}
+
+fn data_mut(lua: &Lua) -> mlua::AppDataRefMut<'_, ConfigData> {
+    lua.app_data_mut::<ConfigData>()
+        .expect("config data is always set")
+}
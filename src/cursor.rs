@@ -0,0 +1,144 @@
+use std::sync::Mutex;
+
+use smithay::backend::allocator::Fourcc;
+use smithay::backend::renderer::element::memory::{
+    MemoryRenderBuffer, MemoryRenderBufferRenderElement,
+};
+use smithay::backend::renderer::element::surface::render_elements_from_surface_tree;
+use smithay::backend::renderer::element::Kind;
+use smithay::backend::renderer::{ImportAll, ImportMem, Renderer, Texture};
+use smithay::desktop::Space;
+use smithay::input::pointer::{CursorImageAttributes, CursorImageStatus};
+use smithay::output::Output;
+use smithay::reexports::wayland_server::Resource;
+use smithay::utils::{Logical, Point, Transform};
+use smithay::wayland::compositor::with_states;
+
+use crate::render::OutputRenderElement;
+use crate::windowdata::CompostoWindow;
+
+/// The arrow shown when no client decides what the pointer looks like, with its hotspot in the
+/// top left corner. `X` is the black outline, `.` the white inside.
+const DEFAULT_CURSOR: [&str; 17] = [
+    "X          ",
+    "XX         ",
+    "X.X        ",
+    "X..X       ",
+    "X...X      ",
+    "X....X     ",
+    "X.....X    ",
+    "X......X   ",
+    "X.......X  ",
+    "X........X ",
+    "X.....XXXXX",
+    "X..X..X    ",
+    "X.X X..X   ",
+    "XX  X..X   ",
+    "X    X..X  ",
+    "     X..X  ",
+    "      XX   ",
+];
+
+/// The pixels of [`DEFAULT_CURSOR`], as the blue, green, red and alpha bytes of `Argb8888`.
+fn default_cursor_pixels() -> Vec<u8> {
+    DEFAULT_CURSOR
+        .iter()
+        .flat_map(|row| row.chars())
+        .flat_map(|pixel| match pixel {
+            'X' => [0, 0, 0, 255],
+            '.' => [255, 255, 255, 255],
+            _ => [0, 0, 0, 0],
+        })
+        .collect()
+}
+
+pub fn default_cursor() -> MemoryRenderBuffer {
+    let size = (DEFAULT_CURSOR[0].len() as i32, DEFAULT_CURSOR.len() as i32);
+    MemoryRenderBuffer::from_slice(
+        &default_cursor_pixels(),
+        Fourcc::Argb8888,
+        size,
+        1,
+        Transform::Normal,
+        None,
+    )
+}
+
+/// Draw the pointer at `location` on `output`, for backends that have no cursor of their own.
+/// A client's cursor surface goes with its hotspot on `location`, anything else gets the default
+/// arrow as there is no cursor theme to draw named cursors from.
+pub fn cursor_elements<R>(
+    output: &Output,
+    space: &Space<CompostoWindow>,
+    status: &CursorImageStatus,
+    default: &MemoryRenderBuffer,
+    location: Point<f64, Logical>,
+    renderer: &mut R,
+) -> Vec<OutputRenderElement<R>>
+where
+    R: Renderer + ImportAll + ImportMem,
+    <R as Renderer>::TextureId: Texture + Clone + Send + 'static,
+{
+    let Some(output_geometry) = space.output_geometry(output) else {
+        return Vec::new();
+    };
+    let scale = output.current_scale().fractional_scale();
+    let location = location - output_geometry.loc.to_f64();
+
+    match status {
+        CursorImageStatus::Hidden => Vec::new(),
+        CursorImageStatus::Surface(surface) if surface.is_alive() => {
+            let hotspot = with_states(surface, |states| {
+                states
+                    .data_map
+                    .get::<Mutex<CursorImageAttributes>>()
+                    .map(|attributes| attributes.lock().unwrap().hotspot)
+                    .unwrap_or_else(|| (0, 0).into())
+            });
+            let location = (location - hotspot.to_f64()).to_physical_precise_round(scale);
+            render_elements_from_surface_tree(renderer, surface, location, scale, 1.0, Kind::Cursor)
+                .into_iter()
+                .map(OutputRenderElement::Surface)
+                .collect()
+        }
+        _ => match MemoryRenderBufferRenderElement::from_buffer(
+            renderer,
+            location.to_physical(scale),
+            default,
+            None,
+            None,
+            None,
+            Kind::Cursor,
+        ) {
+            Ok(element) => vec![OutputRenderElement::Cursor(element)],
+            Err(e) => {
+                eprintln!("render: unable to draw the cursor: {e}");
+                Vec::new()
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_cursor_rows_have_the_same_width() {
+        let width = DEFAULT_CURSOR[0].len();
+        assert!(DEFAULT_CURSOR.iter().all(|row| row.len() == width));
+    }
+
+    #[test]
+    fn default_cursor_pixels_are_argb() {
+        let pixels = default_cursor_pixels();
+        assert_eq!(
+            pixels.len(),
+            DEFAULT_CURSOR[0].len() * DEFAULT_CURSOR.len() * 4
+        );
+        // the hotspot is on the opaque black tip, the top right corner is transparent
+        assert_eq!(pixels[..4], [0, 0, 0, 255]);
+        let width = DEFAULT_CURSOR[0].len();
+        assert_eq!(pixels[(width - 1) * 4..width * 4], [0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,309 @@
+use mlua::Table;
+use smithay::backend::renderer::element::solid::{SolidColorBuffer, SolidColorRenderElement};
+use smithay::backend::renderer::element::Kind;
+pub use smithay::reexports::wayland_protocols::xdg::decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode as DecorationMode;
+use smithay::utils::{Logical, Physical, Point, Rectangle, Scale, Size};
+use smithay::wayland::shell::xdg::ToplevelSurface;
+
+use crate::focus::CompostorFocus;
+use crate::state::{BackendDataExt, Compostate};
+use crate::windowdata::{initial_configure_sent, CompostoWindow};
+
+pub type Color = [f32; 4];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecorationColors {
+    pub border: Color,
+    pub title: Color,
+}
+
+/// How server-side decorations look. Title bars are plain bars, there is no text on them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecorationStyle {
+    pub border_width: i32,
+    /// Height of the title bar, 0 to go without one.
+    pub title_height: i32,
+    pub focused: DecorationColors,
+    pub unfocused: DecorationColors,
+    pub urgent: DecorationColors,
+}
+
+impl Default for DecorationStyle {
+    fn default() -> Self {
+        Self {
+            border_width: 2,
+            title_height: 0,
+            focused: DecorationColors {
+                border: [0.32, 0.58, 0.89, 1.0],
+                title: [0.32, 0.58, 0.89, 1.0],
+            },
+            unfocused: DecorationColors {
+                border: [0.27, 0.27, 0.27, 1.0],
+                title: [0.27, 0.27, 0.27, 1.0],
+            },
+            urgent: DecorationColors {
+                border: [0.84, 0.29, 0.22, 1.0],
+                title: [0.84, 0.29, 0.22, 1.0],
+            },
+        }
+    }
+}
+
+impl DecorationStyle {
+    /// Read the style given to `composto.decorations`, keeping the defaults for missing keys.
+    pub fn from_table(table: Table<'_>) -> mlua::Result<Self> {
+        let mut style = Self::default();
+        if let Some(width) = table.get::<_, Option<i32>>("border_width")? {
+            style.border_width = width.max(0);
+        }
+        if let Some(height) = table.get::<_, Option<i32>>("title_height")? {
+            style.title_height = height.max(0);
+        }
+        for (key, colors) in [
+            ("focused", &mut style.focused),
+            ("unfocused", &mut style.unfocused),
+            ("urgent", &mut style.urgent),
+        ] {
+            let Some(table) = table.get::<_, Option<Table>>(key)? else {
+                continue;
+            };
+            if let Some(border) = table.get::<_, Option<String>>("border")? {
+                colors.border = parse_color(&border)?;
+            }
+            if let Some(title) = table.get::<_, Option<String>>("title")? {
+                colors.title = parse_color(&title)?;
+            }
+        }
+        Ok(style)
+    }
+
+    /// Offset of the window from the top-left corner of its frame, and how much bigger the
+    /// frame is than the window.
+    pub fn insets(&self) -> (Point<i32, Logical>, Size<i32, Logical>) {
+        let width = self.border_width;
+        let height = self.title_height;
+        (
+            (width, width + height).into(),
+            (2 * width, 2 * width + height).into(),
+        )
+    }
+}
+
+/// Parse `#rrggbb` or `#rrggbbaa`.
+pub fn parse_color(color: &str) -> mlua::Result<Color> {
+    let invalid = || mlua::Error::RuntimeError(format!("invalid color `{color}`"));
+    let hex = color.strip_prefix('#').ok_or_else(invalid)?;
+    if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+        return Err(invalid());
+    }
+
+    let mut rgba = [1.0; 4];
+    for (i, channel) in rgba.iter_mut().enumerate().take(hex.len() / 2) {
+        let value = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+        *channel = value as f32 / 255.0;
+    }
+    Ok(rgba)
+}
+
+/// Parse a decoration mode as given in the config, `server` or `client`.
+pub fn parse_decoration_mode(mode: &str) -> mlua::Result<DecorationMode> {
+    match mode {
+        "server" => Ok(DecorationMode::ServerSide),
+        "client" => Ok(DecorationMode::ClientSide),
+        _ => Err(mlua::Error::RuntimeError(format!(
+            "invalid decoration mode `{mode}`, expected `server` or `client`"
+        ))),
+    }
+}
+
+/// Border and title bar of a window, with the buffers they are drawn from so the damage
+/// tracker only sees them change when they really do.
+#[derive(Debug)]
+pub struct Decoration {
+    border_width: i32,
+    title_height: i32,
+    colors: DecorationColors,
+    parts: Vec<SolidColorBuffer>,
+}
+
+impl Decoration {
+    pub fn new(style: &DecorationStyle, colors: DecorationColors) -> Self {
+        Self {
+            border_width: style.border_width,
+            title_height: style.title_height,
+            colors,
+            parts: Vec::new(),
+        }
+    }
+
+    pub fn set_style(&mut self, style: &DecorationStyle, colors: DecorationColors) {
+        self.border_width = style.border_width;
+        self.title_height = style.title_height;
+        self.colors = colors;
+    }
+
+    /// The frame around a window of `size`, relative to the window's top-left corner.
+    pub fn frame(&self, size: Size<i32, Logical>) -> Rectangle<i32, Logical> {
+        let (width, height) = (self.border_width, self.title_height);
+        Rectangle::from_loc_and_size(
+            (-width, -width - height),
+            (size.w + 2 * width, size.h + 2 * width + height),
+        )
+    }
+
+    /// The parts of the frame with their color, relative to the window's top-left corner.
+    fn rects(&self, size: Size<i32, Logical>) -> Vec<(Rectangle<i32, Logical>, Color)> {
+        let frame = self.frame(size);
+        let (width, height) = (self.border_width, self.title_height);
+        let border = self.colors.border;
+
+        let mut parts = Vec::new();
+        if width > 0 {
+            parts.extend([
+                (
+                    Rectangle::from_loc_and_size(frame.loc, (frame.size.w, width)),
+                    border,
+                ),
+                (
+                    Rectangle::from_loc_and_size((frame.loc.x, size.h), (frame.size.w, width)),
+                    border,
+                ),
+                (
+                    Rectangle::from_loc_and_size((frame.loc.x, -height), (width, size.h + height)),
+                    border,
+                ),
+                (
+                    Rectangle::from_loc_and_size((size.w, -height), (width, size.h + height)),
+                    border,
+                ),
+            ]);
+        }
+        if height > 0 {
+            parts.push((
+                Rectangle::from_loc_and_size((0, -height), (size.w, height)),
+                self.colors.title,
+            ));
+        }
+        parts
+    }
+
+    /// Elements drawing the frame of a window of `size` whose top-left corner is at `location`.
+    pub fn render_elements(
+        &mut self,
+        size: Size<i32, Logical>,
+        location: Point<i32, Physical>,
+        scale: Scale<f64>,
+        alpha: f32,
+    ) -> Vec<SolidColorRenderElement> {
+        let rects = self.rects(size);
+        self.parts
+            .resize_with(rects.len(), SolidColorBuffer::default);
+        self.parts
+            .iter_mut()
+            .zip(rects)
+            .map(|(buffer, (rect, color))| {
+                buffer.update(rect.size, color);
+                SolidColorRenderElement::from_buffer(
+                    buffer,
+                    location + rect.loc.to_physical_precise_round(scale),
+                    scale,
+                    alpha,
+                    Kind::Unspecified,
+                )
+            })
+            .collect()
+    }
+}
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    /// The decoration mode of a window: what a window rule or the config says for its app_id, or
+    /// else `requested`.
+    fn decoration_mode(
+        &self,
+        toplevel: &ToplevelSurface,
+        requested: DecorationMode,
+    ) -> DecorationMode {
+        let window = self.window_for_surface(toplevel.wl_surface());
+        if let Some(mode) = window
+            .as_ref()
+            .and_then(|window| window.state().decoration_mode)
+        {
+            return mode;
+        }
+        window
+            .and_then(|window| window.app_id())
+            .and_then(|app_id| {
+                self.config
+                    .with_data(|data| data.decoration_modes.get(&app_id).copied())
+            })
+            .unwrap_or(requested)
+    }
+
+    pub fn set_decoration_mode(&mut self, toplevel: &ToplevelSurface, requested: DecorationMode) {
+        let mode = self.decoration_mode(toplevel, requested);
+        toplevel.with_pending_state(|state| state.decoration_mode = Some(mode));
+        if let Some(window) = self.window_for_surface(toplevel.wl_surface()) {
+            window.state().server_side = mode == DecorationMode::ServerSide;
+        }
+
+        // the frame takes space from the window, so it needs a new size
+        self.update_decorations();
+        self.arrange_all();
+        if initial_configure_sent(toplevel) {
+            toplevel.send_pending_configure();
+        }
+    }
+
+    /// Bring the decorations of every window up to date with its mode, focus and urgency.
+    pub fn update_decorations(&mut self) {
+        let focused = self
+            .seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus());
+        self.update_decorations_for(focused.as_ref());
+    }
+
+    /// Like [`update_decorations`] with the keyboard focus given, for when the keyboard cannot be
+    /// asked because it is busy changing focus.
+    ///
+    /// [`update_decorations`]: Self::update_decorations
+    pub fn update_decorations_for(&mut self, focused: Option<&CompostorFocus>) {
+        let style = self.config.with_data(|data| data.decorations.clone());
+
+        for window in &self.windows {
+            let is_focused = matches!(focused, Some(CompostorFocus::Window(w)) if w == window);
+            let mut state = window.state();
+            if !state.server_side || state.fullscreen {
+                state.decoration = None;
+                continue;
+            }
+
+            let colors = if state.urgent {
+                style.urgent
+            } else if is_focused {
+                style.focused
+            } else {
+                style.unfocused
+            };
+            match &mut state.decoration {
+                Some(decoration) => decoration.set_style(&style, colors),
+                None => state.decoration = Some(Decoration::new(&style, colors)),
+            }
+        }
+        self.schedule_render();
+    }
+
+    /// Offset and extra size of the frame of `window`, nothing for client-side decorations and
+    /// fullscreen windows.
+    pub fn decoration_insets(
+        &self,
+        window: &CompostoWindow,
+    ) -> (Point<i32, Logical>, Size<i32, Logical>) {
+        let state = window.state();
+        if state.server_side && !state.fullscreen {
+            self.config.with_data(|data| data.decorations.insets())
+        } else {
+            Default::default()
+        }
+    }
+}
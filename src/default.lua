@@ -0,0 +1,152 @@
+-- Built-in composto configuration, used when there is no init.lua or it fails to load.
+-- Copy it to $XDG_CONFIG_HOME/composto/init.lua as a starting point for your own.
+
+composto.tags("1", "2", "3", "4", "5", "6", "7", "8", "9")
+
+-- xkb settings of the keyboard; with several layouts, bind a key to switch between them.
+-- composto.keyboard({ layout = "us,de", options = "caps:escape", repeat_rate = 30, repeat_delay = 250 })
+-- composto.bind("Super+space", function()
+--     composto.switch_keyboard_layout()
+-- end)
+
+-- Windows with server-side decorations get a border and, with a title_height, a title bar.
+-- composto.decorations({
+--     border_width = 2,
+--     title_height = 16,
+--     focused = { border = "#5294e2", title = "#5294e2" },
+--     unfocused = { border = "#444444", title = "#444444" },
+--     urgent = { border = "#d64937", title = "#d64937" },
+-- })
+-- composto.decoration_mode("firefox", "client")
+
+-- Windows asking to be activated (a clicked notification, gtk-launch) get the focus when the
+-- request comes from recent input, and are marked urgent otherwise. A policy hook gets the app_id
+-- and whether the request is valid, and returns "focus", "urgent", "ignore" or nil for the default.
+-- composto.activation_policy(function(app_id, valid)
+--     if app_id == "firefox" then
+--         return "urgent"
+--     end
+-- end)
+
+-- Windows get the keyboard when clicked; uncomment to focus whatever is under the pointer instead.
+-- composto.focus_follows_mouse(true)
+
+-- Outputs are scaled by name, fractional scales like 1.25 or 1.5 included.
+-- composto.output_scale("eDP-1", 1.5)
+
+composto.bind("Super+Return", function()
+    composto.spawn("foot")
+end)
+
+composto.bind("Super+Shift+e", function()
+    composto.quit()
+end)
+
+composto.bind("Super+Shift+r", function()
+    composto.reload()
+end)
+
+-- Reload the config whenever it is saved.
+-- composto.auto_reload(true)
+
+for i = 1, 9 do
+    composto.bind("Super+" .. i, function()
+        composto.view_tag(i)
+    end)
+    composto.bind("Super+Ctrl+" .. i, function()
+        composto.toggle_tag(i)
+    end)
+    composto.bind("Super+Shift+" .. i, function()
+        composto.move_to_tag(i)
+    end)
+    composto.bind("Super+Ctrl+Shift+" .. i, function()
+        composto.toggle_window_tag(i)
+    end)
+end
+
+-- Floating windows are left out of the layout. Windows are moved by dragging them with the left
+-- button and resized with the right one while the drag modifier is held, and snap to the edges
+-- of outputs and other windows within snap_threshold pixels.
+composto.bind("Super+Shift+space", function()
+    composto.toggle_floating()
+end)
+-- composto.drag_modifier("Super")
+-- composto.snap_threshold(10)
+
+-- Fullscreen windows cover their whole output, maximized ones all of it but the panels.
+composto.bind("Super+f", function()
+    composto.toggle_fullscreen()
+end)
+composto.bind("Super+Shift+m", function()
+    composto.toggle_maximize()
+end)
+
+composto.bind("Super+t", function()
+    composto.layout("master_stack")
+end)
+composto.bind("Super+m", function()
+    composto.layout("monocle")
+end)
+composto.bind("Super+g", function()
+    composto.layout("grid")
+end)
+composto.bind("Super+i", function()
+    composto.inc_master_count(1)
+end)
+composto.bind("Super+d", function()
+    composto.inc_master_count(-1)
+end)
+composto.bind("Super+h", function()
+    composto.inc_master_ratio(-0.05)
+end)
+composto.bind("Super+l", function()
+    composto.inc_master_ratio(0.05)
+end)
+
+-- Layouts can also be written in lua. A layout gets the usable area, one descriptor per tiled
+-- window (app_id, title, tags, tag) and the master_count/master_ratio of the tag, and returns
+-- one {x, y, w, h} rectangle per window:
+--
+-- composto.define_layout("columns", function(area, windows, params)
+--     local rects = {}
+--     local w = area.w // math.max(#windows, 1)
+--     for i = 1, #windows do
+--         rects[i] = { x = area.x + (i - 1) * w, y = area.y, w = w, h = area.h }
+--     end
+--     return rects
+-- end)
+-- composto.layout("columns")
+
+-- Handlers can be hooked to what happens in the compositor: window_new, window_close,
+-- window_focus, title_changed, app_id_changed (all given the window), tag_changed, output_added,
+-- output_removed (given the output) and client_disconnected (given the pid of the client):
+--
+-- composto.on("window_new", function(window)
+--     if window.app_id == "firefox" then
+--         window:move_to_tag(2)
+--     end
+-- end)
+-- composto.on("output_added", function(output)
+--     print(output.name .. " is " .. output.width .. "x" .. output.height)
+-- end)
+
+-- Window rules set properties of the windows they match when these are mapped, or once a later
+-- title or app_id change makes them match; reloading the config applies its rules to every
+-- window once more. app_id and title are regular expressions, min_size_at_least and
+-- min_size_at_most bound the minimum size the client sets for the window. Rules apply in order:
+--
+-- composto.rule({
+--     match = { app_id = "^pavucontrol$" },
+--     floating = true,
+--     size = { w = 800, h = 500 },
+-- })
+-- composto.rule({
+--     match = { app_id = "firefox", title = "Picture-in-Picture" },
+--     floating = true,
+--     position = { x = 20, y = 20 },
+--     opacity = 0.9,
+--     focus = false,
+-- })
+-- composto.rule({ match = { parent = true }, floating = true })
+-- composto.rule({ match = { app_id = "mpv" }, tags = { 3 }, output = "HDMI-A-1" })
+-- composto.rule({ match = { app_id = "foot" }, decorations = "server" })
@@ -0,0 +1,732 @@
+use smithay::input::pointer::{
+    AxisFrame, ButtonEvent, Focus, GestureHoldBeginEvent, GestureHoldEndEvent,
+    GesturePinchBeginEvent, GesturePinchEndEvent, GesturePinchUpdateEvent, GestureSwipeBeginEvent,
+    GestureSwipeEndEvent, GestureSwipeUpdateEvent, GrabStartData as PointerGrabStartData,
+    MotionEvent, PointerGrab, PointerInnerHandle, RelativeMotionEvent,
+};
+use smithay::input::Seat;
+use smithay::reexports::wayland_protocols::xdg::shell::server::xdg_toplevel::{self, ResizeEdge};
+use smithay::reexports::wayland_server::protocol::wl_seat::WlSeat;
+use smithay::reexports::wayland_server::Resource;
+use smithay::utils::{IsAlive, Logical, Point, Rectangle, Serial, Size};
+use smithay::wayland::compositor::with_states;
+use smithay::wayland::seat::WaylandFocus;
+use smithay::wayland::shell::xdg::{SurfaceCachedState, ToplevelSurface};
+
+use crate::focus::CompostorFocus;
+use crate::keybindings::Modifiers;
+use crate::layout::Placement;
+use crate::state::{BackendDataExt, Compostate};
+use crate::tags::visible_tags;
+use crate::windowdata::CompostoWindow;
+
+pub const DEFAULT_SNAP_THRESHOLD: i32 = 10;
+
+/// Gesture methods of the grabs below. Gestures have nothing to do with moving windows and are
+/// passed on as they come.
+macro_rules! forward_gestures {
+    () => {
+        fn gesture_swipe_begin(
+            &mut self,
+            data: &mut Compostate<BackendData>,
+            handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+            event: &GestureSwipeBeginEvent,
+        ) {
+            handle.gesture_swipe_begin(data, event);
+        }
+
+        fn gesture_swipe_update(
+            &mut self,
+            data: &mut Compostate<BackendData>,
+            handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+            event: &GestureSwipeUpdateEvent,
+        ) {
+            handle.gesture_swipe_update(data, event);
+        }
+
+        fn gesture_swipe_end(
+            &mut self,
+            data: &mut Compostate<BackendData>,
+            handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+            event: &GestureSwipeEndEvent,
+        ) {
+            handle.gesture_swipe_end(data, event);
+        }
+
+        fn gesture_pinch_begin(
+            &mut self,
+            data: &mut Compostate<BackendData>,
+            handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+            event: &GesturePinchBeginEvent,
+        ) {
+            handle.gesture_pinch_begin(data, event);
+        }
+
+        fn gesture_pinch_update(
+            &mut self,
+            data: &mut Compostate<BackendData>,
+            handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+            event: &GesturePinchUpdateEvent,
+        ) {
+            handle.gesture_pinch_update(data, event);
+        }
+
+        fn gesture_pinch_end(
+            &mut self,
+            data: &mut Compostate<BackendData>,
+            handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+            event: &GesturePinchEndEvent,
+        ) {
+            handle.gesture_pinch_end(data, event);
+        }
+
+        fn gesture_hold_begin(
+            &mut self,
+            data: &mut Compostate<BackendData>,
+            handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+            event: &GestureHoldBeginEvent,
+        ) {
+            handle.gesture_hold_begin(data, event);
+        }
+
+        fn gesture_hold_end(
+            &mut self,
+            data: &mut Compostate<BackendData>,
+            handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+            event: &GestureHoldEndEvent,
+        ) {
+            handle.gesture_hold_end(data, event);
+        }
+    };
+}
+
+/// Linux input event codes of the buttons starting modifier drags.
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
+
+/// The edges of a window following the pointer during a resize.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResizeEdges {
+    pub left: bool,
+    pub right: bool,
+    pub top: bool,
+    pub bottom: bool,
+}
+
+impl From<ResizeEdge> for ResizeEdges {
+    fn from(edge: ResizeEdge) -> Self {
+        let bits = edge as u32;
+        Self {
+            top: bits & 1 != 0,
+            bottom: bits & 2 != 0,
+            left: bits & 4 != 0,
+            right: bits & 8 != 0,
+        }
+    }
+}
+
+/// A resize in progress, kept until the client committed the last size it was sent so windows
+/// grabbed by their top or left edge can be moved along with their size.
+#[derive(Debug, Clone, Copy)]
+pub struct ResizeState {
+    pub edges: ResizeEdges,
+    /// Location and size of the window when the resize started.
+    pub initial: Rectangle<i32, Logical>,
+    /// Set once the pointer let go of the window.
+    pub released: bool,
+}
+
+/// Drags a floating window around with the pointer.
+pub struct MoveGrab<BackendData: BackendDataExt + 'static> {
+    start_data: PointerGrabStartData<Compostate<BackendData>>,
+    window: CompostoWindow,
+    initial_location: Point<i32, Logical>,
+}
+
+impl<BackendData: BackendDataExt + 'static> PointerGrab<Compostate<BackendData>>
+    for MoveGrab<BackendData>
+{
+    fn motion(
+        &mut self,
+        data: &mut Compostate<BackendData>,
+        handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+        _focus: Option<(CompostorFocus, Point<i32, Logical>)>,
+        event: &MotionEvent,
+    ) {
+        // no client gets the pointer while it is dragging a window
+        handle.motion(data, None, event);
+        if !self.window.alive() {
+            handle.unset_grab(data, event.serial, event.time, true);
+            return;
+        }
+        let delta = event.location - self.start_data.location;
+        data.move_floating_window(&self.window, self.initial_location + delta.to_i32_round());
+    }
+
+    fn relative_motion(
+        &mut self,
+        data: &mut Compostate<BackendData>,
+        handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+        _focus: Option<(CompostorFocus, Point<i32, Logical>)>,
+        event: &RelativeMotionEvent,
+    ) {
+        handle.relative_motion(data, None, event);
+    }
+
+    fn button(
+        &mut self,
+        data: &mut Compostate<BackendData>,
+        handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+        event: &ButtonEvent,
+    ) {
+        handle.button(data, event);
+        if handle.current_pressed().is_empty() {
+            handle.unset_grab(data, event.serial, event.time, true);
+        }
+    }
+
+    fn axis(
+        &mut self,
+        data: &mut Compostate<BackendData>,
+        handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+        details: AxisFrame,
+    ) {
+        handle.axis(data, details);
+    }
+
+    fn frame(
+        &mut self,
+        data: &mut Compostate<BackendData>,
+        handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+    ) {
+        handle.frame(data);
+    }
+
+    forward_gestures!();
+
+    fn start_data(&self) -> &PointerGrabStartData<Compostate<BackendData>> {
+        &self.start_data
+    }
+}
+
+/// Resizes a floating window by the edges in its [`ResizeState`].
+pub struct ResizeGrab<BackendData: BackendDataExt + 'static> {
+    start_data: PointerGrabStartData<Compostate<BackendData>>,
+    window: CompostoWindow,
+}
+
+impl<BackendData: BackendDataExt + 'static> PointerGrab<Compostate<BackendData>>
+    for ResizeGrab<BackendData>
+{
+    fn motion(
+        &mut self,
+        data: &mut Compostate<BackendData>,
+        handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+        _focus: Option<(CompostorFocus, Point<i32, Logical>)>,
+        event: &MotionEvent,
+    ) {
+        handle.motion(data, None, event);
+        if !self.window.alive() {
+            handle.unset_grab(data, event.serial, event.time, true);
+            return;
+        }
+        let delta = event.location - self.start_data.location;
+        data.resize_floating_window(&self.window, delta.to_i32_round());
+    }
+
+    fn relative_motion(
+        &mut self,
+        data: &mut Compostate<BackendData>,
+        handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+        _focus: Option<(CompostorFocus, Point<i32, Logical>)>,
+        event: &RelativeMotionEvent,
+    ) {
+        handle.relative_motion(data, None, event);
+    }
+
+    fn button(
+        &mut self,
+        data: &mut Compostate<BackendData>,
+        handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+        event: &ButtonEvent,
+    ) {
+        handle.button(data, event);
+        if handle.current_pressed().is_empty() {
+            handle.unset_grab(data, event.serial, event.time, true);
+            data.end_resize(&self.window);
+        }
+    }
+
+    fn axis(
+        &mut self,
+        data: &mut Compostate<BackendData>,
+        handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+        details: AxisFrame,
+    ) {
+        handle.axis(data, details);
+    }
+
+    fn frame(
+        &mut self,
+        data: &mut Compostate<BackendData>,
+        handle: &mut PointerInnerHandle<'_, Compostate<BackendData>>,
+    ) {
+        handle.frame(data);
+    }
+
+    forward_gestures!();
+
+    fn start_data(&self) -> &PointerGrabStartData<Compostate<BackendData>> {
+        &self.start_data
+    }
+}
+
+/// Minimum and maximum size the client accepts, zero meaning no bound.
+fn size_hints(toplevel: &ToplevelSurface) -> (Size<i32, Logical>, Size<i32, Logical>) {
+    with_states(toplevel.wl_surface(), |states| {
+        let cached = states.cached_state.current::<SurfaceCachedState>();
+        (cached.min_size, cached.max_size)
+    })
+}
+
+/// Clamp a length to the size hints of a client.
+fn clamp_length(length: i32, min: i32, max: i32) -> i32 {
+    let length = length.max(min.max(1));
+    if max > 0 {
+        length.min(max)
+    } else {
+        length
+    }
+}
+
+/// The smallest distance from one of `edges` to one of `targets`, if it is within `threshold`.
+fn snap_offset(edges: &[i32], targets: &[i32], threshold: i32) -> Option<i32> {
+    edges
+        .iter()
+        .flat_map(|edge| targets.iter().map(move |target| target - edge))
+        .filter(|offset| offset.abs() <= threshold)
+        .min_by_key(|offset| offset.abs())
+}
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    pub fn toggle_floating(&mut self) {
+        if let Some(window) = self.focused_window() {
+            let floating = window.state().floating;
+            self.set_floating(&window, !floating);
+        }
+    }
+
+    /// Take a window out of the layout or put it back in. Windows start floating where and as big
+    /// as they are at that point.
+    pub fn set_floating(&mut self, window: &CompostoWindow, floating: bool) {
+        let location = self.space.element_location(window);
+        {
+            let mut state = window.state();
+            if state.floating == floating {
+                return;
+            }
+            state.floating = floating;
+            if floating {
+                if let Some(location) = location {
+                    state.location = location;
+                }
+                let size = window.0.geometry().size;
+                if size.w > 0 && size.h > 0 {
+                    state.floating_size = Some(size);
+                }
+            }
+        }
+        self.arrange_all();
+    }
+
+    /// Put the windows back in the stacking order of their [`Placement`], keeping their order
+    /// within each placement: maximized windows above tiled ones, floating windows above those
+    /// and fullscreen windows on top.
+    pub fn restack(&mut self) {
+        let mut above_tiled = self
+            .space
+            .elements()
+            .filter(|window| window.state().placement() != Placement::Tiled)
+            .cloned()
+            .collect::<Vec<_>>();
+        // stable, so windows of the same placement stay in order
+        above_tiled.sort_by_key(|window| window.state().placement());
+        for window in above_tiled {
+            self.space.raise_element(&window, false);
+        }
+    }
+
+    /// The frame of `window` if it was at `location`: the window with its decorations.
+    fn window_frame(
+        &self,
+        window: &CompostoWindow,
+        location: Point<i32, Logical>,
+    ) -> Rectangle<i32, Logical> {
+        let (offset, extra) = self.decoration_insets(window);
+        Rectangle::from_loc_and_size(location - offset, window.0.geometry().size + extra)
+    }
+
+    /// Edges floating windows snap to: the usable area of the output of `window` and the frames
+    /// of the other windows shown on it, as (x, y) coordinates.
+    fn snap_targets(&self, window: &CompostoWindow) -> (Vec<i32>, Vec<i32>) {
+        let (mut xs, mut ys) = (Vec::new(), Vec::new());
+        let Some(output) = self.window_output(window) else {
+            return (xs, ys);
+        };
+        let mut rects = self.usable_area(&output).into_iter().collect::<Vec<_>>();
+        rects.extend(
+            self.space
+                .elements_for_output(&output)
+                .filter(|other| *other != window)
+                .filter_map(|other| {
+                    let location = self.space.element_location(other)?;
+                    Some(self.window_frame(other, location))
+                }),
+        );
+        for rect in rects {
+            xs.extend([rect.loc.x, rect.loc.x + rect.size.w]);
+            ys.extend([rect.loc.y, rect.loc.y + rect.size.h]);
+        }
+        (xs, ys)
+    }
+
+    fn snap_threshold(&self) -> i32 {
+        self.config.with_data(|data| data.snap_threshold)
+    }
+
+    /// Move a floating window to `location`, snapping its frame to nearby edges.
+    pub fn move_floating_window(&mut self, window: &CompostoWindow, location: Point<i32, Logical>) {
+        let frame = self.window_frame(window, location);
+        let (xs, ys) = self.snap_targets(window);
+        let threshold = self.snap_threshold();
+        let right = frame.loc.x + frame.size.w;
+        let bottom = frame.loc.y + frame.size.h;
+        let location = location
+            + Point::from((
+                snap_offset(&[frame.loc.x, right], &xs, threshold).unwrap_or(0),
+                snap_offset(&[frame.loc.y, bottom], &ys, threshold).unwrap_or(0),
+            ));
+
+        // a window dragged onto another output goes to the tags shown there, to stay visible
+        let output = self.space.output_under(location.to_f64()).next().cloned();
+        {
+            let mut state = window.state();
+            state.location = location;
+            if let Some(output) = &output {
+                if state.output.as_deref() != Some(output.name().as_str()) {
+                    state.output = Some(output.name());
+                    state.tags = visible_tags(output);
+                }
+            }
+        }
+        self.space.map_element(window.clone(), location, false);
+        if let Some(output) = &output {
+            self.update_surface_scales(output);
+        }
+        self.schedule_render();
+    }
+
+    /// Start moving `window` with the pointer. Tiled and maximized windows become floating,
+    /// fullscreen windows are not moved.
+    pub fn start_move(
+        &mut self,
+        window: &CompostoWindow,
+        start_data: PointerGrabStartData<Self>,
+        serial: Serial,
+    ) {
+        let Some(pointer) = self.seat.get_pointer() else {
+            return;
+        };
+        if !self.grab_floating(window) {
+            return;
+        }
+        let Some(initial_location) = self.space.element_location(window) else {
+            return;
+        };
+        let grab = MoveGrab {
+            start_data,
+            window: window.clone(),
+            initial_location,
+        };
+        pointer.set_grab(self, grab, serial, Focus::Clear);
+    }
+
+    /// Start resizing `window` by `edges` with the pointer, with the same rules as [`start_move`].
+    ///
+    /// [`start_move`]: Self::start_move
+    pub fn start_resize(
+        &mut self,
+        window: &CompostoWindow,
+        edges: ResizeEdges,
+        start_data: PointerGrabStartData<Self>,
+        serial: Serial,
+    ) {
+        let (Some(pointer), Some(toplevel)) = (self.seat.get_pointer(), window.0.toplevel()) else {
+            return;
+        };
+        if !self.grab_floating(window) {
+            return;
+        }
+        let Some(location) = self.space.element_location(window) else {
+            return;
+        };
+
+        window.state().resize = Some(ResizeState {
+            edges,
+            initial: Rectangle::from_loc_and_size(location, window.0.geometry().size),
+            released: false,
+        });
+        toplevel.with_pending_state(|state| {
+            state.states.set(xdg_toplevel::State::Resizing);
+        });
+        toplevel.send_pending_configure();
+
+        let grab = ResizeGrab {
+            start_data,
+            window: window.clone(),
+        };
+        pointer.set_grab(self, grab, serial, Focus::Clear);
+    }
+
+    /// Make `window` floating and not maximized for a pointer grab, unless it is fullscreen.
+    fn grab_floating(&mut self, window: &CompostoWindow) -> bool {
+        if window.state().fullscreen {
+            return false;
+        }
+        let maximized = std::mem::take(&mut window.state().maximized);
+        self.set_floating(window, true);
+        if maximized {
+            self.arrange_all();
+        }
+        true
+    }
+
+    /// Follow the pointer `delta` away from where the resize of `window` started, snapping the
+    /// edges being dragged to nearby edges.
+    pub fn resize_floating_window(&mut self, window: &CompostoWindow, delta: Point<i32, Logical>) {
+        let Some(resize) = window.state().resize else {
+            return;
+        };
+        let Some(toplevel) = window.0.toplevel() else {
+            return;
+        };
+        let (edges, initial) = (resize.edges, resize.initial);
+        let (_, extra) = self.decoration_insets(window);
+        let frame = self.window_frame(window, initial.loc);
+        let (xs, ys) = self.snap_targets(window);
+        let threshold = self.snap_threshold();
+
+        // work on the frame, so it is the frame that snaps
+        let (mut left, mut top) = (frame.loc.x, frame.loc.y);
+        let (mut right, mut bottom) = (
+            left + initial.size.w + extra.w,
+            top + initial.size.h + extra.h,
+        );
+        if edges.left {
+            left += delta.x;
+            left += snap_offset(&[left], &xs, threshold).unwrap_or(0);
+        } else if edges.right {
+            right += delta.x;
+            right += snap_offset(&[right], &xs, threshold).unwrap_or(0);
+        }
+        if edges.top {
+            top += delta.y;
+            top += snap_offset(&[top], &ys, threshold).unwrap_or(0);
+        } else if edges.bottom {
+            bottom += delta.y;
+            bottom += snap_offset(&[bottom], &ys, threshold).unwrap_or(0);
+        }
+
+        let (min, max) = size_hints(toplevel);
+        let size = Size::from((
+            clamp_length(right - left - extra.w, min.w, max.w),
+            clamp_length(bottom - top - extra.h, min.h, max.h),
+        ));
+        window.state().floating_size = Some(size);
+        toplevel.with_pending_state(|state| state.size = Some(size));
+        toplevel.send_pending_configure();
+    }
+
+    /// The pointer let go of `window`, which stops being resized once it has caught up.
+    pub fn end_resize(&mut self, window: &CompostoWindow) {
+        if let Some(resize) = &mut window.state().resize {
+            resize.released = true;
+        }
+        if let Some(toplevel) = window.0.toplevel() {
+            toplevel.with_pending_state(|state| {
+                state.states.unset(xdg_toplevel::State::Resizing);
+            });
+            toplevel.send_pending_configure();
+        }
+    }
+
+    /// Keep the edges opposite to the ones being dragged in place while the client catches up
+    /// with the sizes it is sent.
+    pub fn resize_commit(&mut self, window: &CompostoWindow) {
+        let Some(resize) = window.state().resize else {
+            return;
+        };
+        let size = window.0.geometry().size;
+        let mut location = resize.initial.loc;
+        if resize.edges.left {
+            location.x += resize.initial.size.w - size.w;
+        }
+        if resize.edges.top {
+            location.y += resize.initial.size.h - size.h;
+        }
+
+        {
+            let mut state = window.state();
+            state.location = location;
+            if resize.released {
+                state.resize = None;
+            }
+        }
+        if self.space.element_location(window) != Some(location) {
+            self.space.map_element(window.clone(), location, false);
+        }
+    }
+
+    /// The pointer grab started by the button press with `serial`, if it was on `surface`. Clients
+    /// may only start moves and resizes from an implicit grab of their own.
+    fn client_grab_start(
+        &self,
+        seat: &WlSeat,
+        toplevel: &ToplevelSurface,
+        serial: Serial,
+    ) -> Option<(CompostoWindow, PointerGrabStartData<Self>)> {
+        let pointer = Seat::<Self>::from_resource(seat)?.get_pointer()?;
+        if !pointer.has_grab(serial) {
+            return None;
+        }
+        let start_data = pointer.grab_start_data()?;
+        let (focus, _) = start_data.focus.as_ref()?;
+        if !focus.same_client_as(&toplevel.wl_surface().id()) {
+            return None;
+        }
+        let window = self.window_for_surface(toplevel.wl_surface())?;
+        Some((window, start_data))
+    }
+
+    pub fn client_move_request(
+        &mut self,
+        toplevel: &ToplevelSurface,
+        seat: &WlSeat,
+        serial: Serial,
+    ) {
+        if let Some((window, start_data)) = self.client_grab_start(seat, toplevel, serial) {
+            self.start_move(&window, start_data, serial);
+        }
+    }
+
+    pub fn client_resize_request(
+        &mut self,
+        toplevel: &ToplevelSurface,
+        seat: &WlSeat,
+        serial: Serial,
+        edge: ResizeEdge,
+    ) {
+        if let Some((window, start_data)) = self.client_grab_start(seat, toplevel, serial) {
+            self.start_resize(&window, edge.into(), start_data, serial);
+        }
+    }
+
+    /// Start a move or resize when a button is pressed with the drag modifier held over a window:
+    /// the left button moves it, the right one resizes it by the corner closest to the pointer.
+    /// Returns whether a grab was started.
+    pub fn modifier_drag(&mut self, button: u32, serial: Serial) -> bool {
+        let (Some(keyboard), Some(pointer)) = (self.seat.get_keyboard(), self.seat.get_pointer())
+        else {
+            return false;
+        };
+        let modifier = self.config.with_data(|data| data.drag_modifier);
+        if Modifiers::from(&keyboard.modifier_state()) != modifier {
+            return false;
+        }
+        let location = pointer.current_location();
+        let Some((window, window_location)) = self
+            .space
+            .element_under(location)
+            .map(|(window, location)| (window.clone(), location))
+        else {
+            return false;
+        };
+
+        let start_data = PointerGrabStartData {
+            focus: None,
+            button,
+            location,
+        };
+        match button {
+            BTN_LEFT => self.start_move(&window, start_data, serial),
+            BTN_RIGHT => {
+                let size = window.0.geometry().size;
+                let relative = location - window_location.to_f64();
+                let edges = ResizeEdges {
+                    left: relative.x < size.w as f64 / 2.0,
+                    right: relative.x >= size.w as f64 / 2.0,
+                    top: relative.y < size.h as f64 / 2.0,
+                    bottom: relative.y >= size.h as f64 / 2.0,
+                };
+                self.start_resize(&window, edges, start_data, serial);
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_edges_from_protocol() {
+        assert_eq!(ResizeEdges::from(ResizeEdge::None), ResizeEdges::default());
+        assert_eq!(
+            ResizeEdges::from(ResizeEdge::Top),
+            ResizeEdges {
+                top: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            ResizeEdges::from(ResizeEdge::BottomRight),
+            ResizeEdges {
+                bottom: true,
+                right: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            ResizeEdges::from(ResizeEdge::TopLeft),
+            ResizeEdges {
+                top: true,
+                left: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn clamp_length_to_hints() {
+        assert_eq!(clamp_length(50, 100, 0), 100);
+        assert_eq!(clamp_length(50, 0, 0), 50);
+        assert_eq!(clamp_length(500, 100, 300), 300);
+        assert_eq!(clamp_length(-5, 0, 0), 1);
+    }
+
+    #[test]
+    fn snap_to_the_closest_target() {
+        assert_eq!(snap_offset(&[100, 300], &[0, 105, 1000], 10), Some(5));
+        assert_eq!(snap_offset(&[98, 300], &[90, 303], 10), Some(3));
+        assert_eq!(snap_offset(&[98], &[90], 10), Some(-8));
+        assert_eq!(snap_offset(&[100], &[105], 5), Some(5));
+    }
+
+    #[test]
+    fn no_snap_beyond_threshold() {
+        assert_eq!(snap_offset(&[100, 300], &[0, 105, 1000], 4), None);
+        assert_eq!(snap_offset(&[100], &[], 10), None);
+    }
+}
@@ -1,26 +1,55 @@
 use smithay::{
+    desktop::{LayerSurface, PopupKind},
     input::{keyboard::KeyboardTarget, pointer::PointerTarget, touch::TouchTarget},
+    reexports::wayland_server::{protocol::wl_surface::WlSurface, Resource},
     utils::IsAlive,
     wayland::seat::WaylandFocus,
 };
 
 use crate::state::{BackendDataExt, Compostate};
+use crate::windowdata::CompostoWindow;
 
+/// Anything the seat can focus. Events are forwarded to the main surface of the target.
 #[derive(Debug, Clone, PartialEq)]
 pub enum CompostorFocus {
-    Window(u8),
-    Layer(u8),
-    Popup(u8),
+    Window(CompostoWindow),
+    Layer(LayerSurface),
+    Popup(PopupKind),
+    /// A subsurface of any of the others. Only the pointer ever goes there, the keyboard stays
+    /// with the surface it hangs off.
+    Surface(WlSurface),
 }
 
-impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for CompostorFocus {
+impl From<CompostoWindow> for CompostorFocus {
+    fn from(window: CompostoWindow) -> Self {
+        CompostorFocus::Window(window)
+    }
+}
+
+impl From<LayerSurface> for CompostorFocus {
+    fn from(layer: LayerSurface) -> Self {
+        CompostorFocus::Layer(layer)
+    }
+}
+
+impl From<PopupKind> for CompostorFocus {
+    fn from(popup: PopupKind) -> Self {
+        CompostorFocus::Popup(popup)
+    }
+}
+
+impl<BackendData: BackendDataExt + 'static> PointerTarget<Compostate<BackendData>>
+    for CompostorFocus
+{
     fn enter(
         &self,
         seat: &smithay::input::Seat<Compostate<BackendData>>,
         data: &mut Compostate<BackendData>,
         event: &smithay::input::pointer::MotionEvent,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::enter(&surface, seat, data, event);
+        }
     }
 
     fn motion(
@@ -29,7 +58,9 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         data: &mut Compostate<BackendData>,
         event: &smithay::input::pointer::MotionEvent,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::motion(&surface, seat, data, event);
+        }
     }
 
     fn relative_motion(
@@ -38,7 +69,9 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         data: &mut Compostate<BackendData>,
         event: &smithay::input::pointer::RelativeMotionEvent,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::relative_motion(&surface, seat, data, event);
+        }
     }
 
     fn button(
@@ -47,7 +80,9 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         data: &mut Compostate<BackendData>,
         event: &smithay::input::pointer::ButtonEvent,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::button(&surface, seat, data, event);
+        }
     }
 
     fn axis(
@@ -56,7 +91,9 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         data: &mut Compostate<BackendData>,
         frame: smithay::input::pointer::AxisFrame,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::axis(&surface, seat, data, frame);
+        }
     }
 
     fn frame(
@@ -64,7 +101,9 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         seat: &smithay::input::Seat<Compostate<BackendData>>,
         data: &mut Compostate<BackendData>,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::frame(&surface, seat, data);
+        }
     }
 
     fn gesture_swipe_begin(
@@ -73,7 +112,9 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         data: &mut Compostate<BackendData>,
         event: &smithay::input::pointer::GestureSwipeBeginEvent,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::gesture_swipe_begin(&surface, seat, data, event);
+        }
     }
 
     fn gesture_swipe_update(
@@ -82,7 +123,9 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         data: &mut Compostate<BackendData>,
         event: &smithay::input::pointer::GestureSwipeUpdateEvent,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::gesture_swipe_update(&surface, seat, data, event);
+        }
     }
 
     fn gesture_swipe_end(
@@ -91,7 +134,9 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         data: &mut Compostate<BackendData>,
         event: &smithay::input::pointer::GestureSwipeEndEvent,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::gesture_swipe_end(&surface, seat, data, event);
+        }
     }
 
     fn gesture_pinch_begin(
@@ -100,7 +145,9 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         data: &mut Compostate<BackendData>,
         event: &smithay::input::pointer::GesturePinchBeginEvent,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::gesture_pinch_begin(&surface, seat, data, event);
+        }
     }
 
     fn gesture_pinch_update(
@@ -109,7 +156,9 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         data: &mut Compostate<BackendData>,
         event: &smithay::input::pointer::GesturePinchUpdateEvent,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::gesture_pinch_update(&surface, seat, data, event);
+        }
     }
 
     fn gesture_pinch_end(
@@ -118,7 +167,9 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         data: &mut Compostate<BackendData>,
         event: &smithay::input::pointer::GesturePinchEndEvent,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::gesture_pinch_end(&surface, seat, data, event);
+        }
     }
 
     fn gesture_hold_begin(
@@ -127,7 +178,9 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         data: &mut Compostate<BackendData>,
         event: &smithay::input::pointer::GestureHoldBeginEvent,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::gesture_hold_begin(&surface, seat, data, event);
+        }
     }
 
     fn gesture_hold_end(
@@ -136,7 +189,9 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         data: &mut Compostate<BackendData>,
         event: &smithay::input::pointer::GestureHoldEndEvent,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::gesture_hold_end(&surface, seat, data, event);
+        }
     }
 
     fn leave(
@@ -146,11 +201,15 @@ impl<BackendData: BackendDataExt> PointerTarget<Compostate<BackendData>> for Com
         serial: smithay::utils::Serial,
         time: u32,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            PointerTarget::leave(&surface, seat, data, serial, time);
+        }
     }
 }
 
-impl<BackendData: BackendDataExt> KeyboardTarget<Compostate<BackendData>> for CompostorFocus {
+impl<BackendData: BackendDataExt + 'static> KeyboardTarget<Compostate<BackendData>>
+    for CompostorFocus
+{
     fn enter(
         &self,
         seat: &smithay::input::Seat<Compostate<BackendData>>,
@@ -158,7 +217,9 @@ impl<BackendData: BackendDataExt> KeyboardTarget<Compostate<BackendData>> for Co
         keys: Vec<smithay::input::keyboard::KeysymHandle<'_>>,
         serial: smithay::utils::Serial,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            KeyboardTarget::enter(&surface, seat, data, keys, serial);
+        }
     }
 
     fn leave(
@@ -167,7 +228,9 @@ impl<BackendData: BackendDataExt> KeyboardTarget<Compostate<BackendData>> for Co
         data: &mut Compostate<BackendData>,
         serial: smithay::utils::Serial,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            KeyboardTarget::leave(&surface, seat, data, serial);
+        }
     }
 
     fn key(
@@ -179,7 +242,9 @@ impl<BackendData: BackendDataExt> KeyboardTarget<Compostate<BackendData>> for Co
         serial: smithay::utils::Serial,
         time: u32,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            KeyboardTarget::key(&surface, seat, data, key, state, serial, time);
+        }
     }
 
     fn modifiers(
@@ -189,11 +254,15 @@ impl<BackendData: BackendDataExt> KeyboardTarget<Compostate<BackendData>> for Co
         modifiers: smithay::input::keyboard::ModifiersState,
         serial: smithay::utils::Serial,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            KeyboardTarget::modifiers(&surface, seat, data, modifiers, serial);
+        }
     }
 }
 
-impl<BackendData: BackendDataExt> TouchTarget<Compostate<BackendData>> for CompostorFocus {
+impl<BackendData: BackendDataExt + 'static> TouchTarget<Compostate<BackendData>>
+    for CompostorFocus
+{
     fn down(
         &self,
         seat: &smithay::input::Seat<Compostate<BackendData>>,
@@ -201,7 +270,9 @@ impl<BackendData: BackendDataExt> TouchTarget<Compostate<BackendData>> for Compo
         event: &smithay::input::touch::DownEvent,
         seq: smithay::utils::Serial,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            TouchTarget::down(&surface, seat, data, event, seq);
+        }
     }
 
     fn up(
@@ -211,7 +282,9 @@ impl<BackendData: BackendDataExt> TouchTarget<Compostate<BackendData>> for Compo
         event: &smithay::input::touch::UpEvent,
         seq: smithay::utils::Serial,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            TouchTarget::up(&surface, seat, data, event, seq);
+        }
     }
 
     fn motion(
@@ -221,7 +294,9 @@ impl<BackendData: BackendDataExt> TouchTarget<Compostate<BackendData>> for Compo
         event: &smithay::input::touch::MotionEvent,
         seq: smithay::utils::Serial,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            TouchTarget::motion(&surface, seat, data, event, seq);
+        }
     }
 
     fn frame(
@@ -230,7 +305,9 @@ impl<BackendData: BackendDataExt> TouchTarget<Compostate<BackendData>> for Compo
         data: &mut Compostate<BackendData>,
         seq: smithay::utils::Serial,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            TouchTarget::frame(&surface, seat, data, seq);
+        }
     }
 
     fn cancel(
@@ -239,7 +316,9 @@ impl<BackendData: BackendDataExt> TouchTarget<Compostate<BackendData>> for Compo
         data: &mut Compostate<BackendData>,
         seq: smithay::utils::Serial,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            TouchTarget::cancel(&surface, seat, data, seq);
+        }
     }
 
     fn shape(
@@ -249,7 +328,9 @@ impl<BackendData: BackendDataExt> TouchTarget<Compostate<BackendData>> for Compo
         event: &smithay::input::touch::ShapeEvent,
         seq: smithay::utils::Serial,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            TouchTarget::shape(&surface, seat, data, event, seq);
+        }
     }
 
     fn orientation(
@@ -259,20 +340,33 @@ impl<BackendData: BackendDataExt> TouchTarget<Compostate<BackendData>> for Compo
         event: &smithay::input::touch::OrientationEvent,
         seq: smithay::utils::Serial,
     ) {
-        todo!()
+        if let Some(surface) = self.wl_surface() {
+            TouchTarget::orientation(&surface, seat, data, event, seq);
+        }
     }
 }
 
 impl IsAlive for CompostorFocus {
     fn alive(&self) -> bool {
-        todo!()
+        match self {
+            CompostorFocus::Window(window) => window.alive(),
+            CompostorFocus::Layer(layer) => layer.alive(),
+            CompostorFocus::Popup(popup) => popup.alive(),
+            CompostorFocus::Surface(surface) => surface.is_alive(),
+        }
     }
 }
 
 impl WaylandFocus for CompostorFocus {
-    fn wl_surface(
-        &self,
-    ) -> Option<smithay::reexports::wayland_server::protocol::wl_surface::WlSurface> {
-        None
+    fn wl_surface(&self) -> Option<WlSurface> {
+        match self {
+            CompostorFocus::Window(window) => window
+                .0
+                .toplevel()
+                .map(|toplevel| toplevel.wl_surface().clone()),
+            CompostorFocus::Layer(layer) => Some(layer.wl_surface().clone()),
+            CompostorFocus::Popup(popup) => Some(popup.wl_surface().clone()),
+            CompostorFocus::Surface(surface) => Some(surface.clone()),
+        }
     }
 }
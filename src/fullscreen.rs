@@ -0,0 +1,89 @@
+use smithay::output::Output;
+use smithay::reexports::wayland_server::protocol::wl_output::WlOutput;
+use smithay::utils::SERIAL_COUNTER;
+use smithay::wayland::shell::xdg::ToplevelSurface;
+
+use crate::state::{BackendDataExt, Compostate};
+use crate::tags::visible_tags;
+use crate::windowdata::{initial_configure_sent, CompostoWindow};
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    pub fn toggle_fullscreen(&mut self) {
+        if let Some(window) = self.focused_window() {
+            let fullscreen = window.state().fullscreen;
+            self.set_fullscreen(&window, !fullscreen, None);
+        }
+    }
+
+    pub fn toggle_maximized(&mut self) {
+        if let Some(window) = self.focused_window() {
+            let maximized = window.state().maximized;
+            self.set_maximized(&window, !maximized);
+        }
+    }
+
+    /// Make `window` cover `output`, or the output it is on when none is given. Moving to another
+    /// output puts the window on the tags shown there. Once it leaves fullscreen, the window goes
+    /// back to being tiled, floating or maximized, as it was before.
+    pub fn set_fullscreen(
+        &mut self,
+        window: &CompostoWindow,
+        fullscreen: bool,
+        output: Option<Output>,
+    ) {
+        if let Some(output) = output.filter(|_| fullscreen) {
+            if self.window_output(window).as_ref() != Some(&output) {
+                let mut state = window.state();
+                state.output = Some(output.name());
+                state.tags = visible_tags(&output);
+            }
+        }
+        window.state().fullscreen = fullscreen;
+
+        // fullscreen windows go without their frame
+        self.update_decorations();
+        self.update_visibility();
+        if fullscreen && self.is_window_visible(window) {
+            self.focus_window(window, SERIAL_COUNTER.next_serial());
+        }
+    }
+
+    /// Make `window` fill the usable area of its output, or give it back its tiled or floating
+    /// geometry.
+    pub fn set_maximized(&mut self, window: &CompostoWindow, maximized: bool) {
+        window.state().maximized = maximized;
+        self.arrange_all();
+    }
+
+    fn window_for_toplevel(&self, toplevel: &ToplevelSurface) -> Option<CompostoWindow> {
+        self.window_for_surface(toplevel.wl_surface())
+    }
+
+    pub fn client_fullscreen_request(
+        &mut self,
+        toplevel: &ToplevelSurface,
+        fullscreen: bool,
+        output: Option<WlOutput>,
+    ) {
+        if let Some(window) = self.window_for_toplevel(toplevel) {
+            let output = output.as_ref().and_then(Output::from_resource);
+            self.set_fullscreen(&window, fullscreen, output);
+        }
+        reply_configure(toplevel);
+    }
+
+    pub fn client_maximize_request(&mut self, toplevel: &ToplevelSurface, maximized: bool) {
+        if let Some(window) = self.window_for_toplevel(toplevel) {
+            self.set_maximized(&window, maximized);
+        }
+        reply_configure(toplevel);
+    }
+}
+
+/// Clients get a configure in response to every state request, even when nothing changed or
+/// the window is hidden and was not arranged.
+fn reply_configure(toplevel: &ToplevelSurface) {
+    if initial_configure_sent(toplevel) && toplevel.send_pending_configure().is_none() {
+        toplevel.send_configure();
+    }
+}
@@ -0,0 +1,234 @@
+use mlua::{Function, IntoLuaMulti, UserData, UserDataFields, UserDataMethods, Value};
+use smithay::output::Output;
+use smithay::reexports::wayland_server::backend::ClientId;
+
+use crate::action::{queue_action, Action};
+use crate::config::tag_mask;
+use crate::ipc::{tag_indices, Event};
+use crate::scale::{MAX_SCALE, MIN_SCALE};
+use crate::state::{BackendDataExt, Compostate};
+use crate::tags::visible_tags;
+use crate::windowdata::CompostoWindow;
+
+/// Something happening in the compositor that the config can react to with `composto.on`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hook {
+    WindowNew,
+    WindowClose,
+    WindowFocus,
+    TitleChanged,
+    AppIdChanged,
+    TagChanged,
+    OutputAdded,
+    OutputRemoved,
+    ClientDisconnected,
+}
+
+impl Hook {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "window_new" => Some(Self::WindowNew),
+            "window_close" => Some(Self::WindowClose),
+            "window_focus" => Some(Self::WindowFocus),
+            "title_changed" => Some(Self::TitleChanged),
+            "app_id_changed" => Some(Self::AppIdChanged),
+            "tag_changed" => Some(Self::TagChanged),
+            "output_added" => Some(Self::OutputAdded),
+            "output_removed" => Some(Self::OutputRemoved),
+            "client_disconnected" => Some(Self::ClientDisconnected),
+            _ => None,
+        }
+    }
+}
+
+/// A window as handed to hooks. The fields are a snapshot taken when the hook fires, the
+/// methods queue actions which run once the hook returns, like everything else lua asks for.
+#[derive(Debug, Clone)]
+pub struct LuaWindow {
+    id: u64,
+    app_id: Option<String>,
+    title: Option<String>,
+    tags: u32,
+    output: Option<String>,
+}
+
+impl From<&CompostoWindow> for LuaWindow {
+    fn from(window: &CompostoWindow) -> Self {
+        let state = window.state();
+        Self {
+            id: state.id,
+            app_id: window.app_id(),
+            title: window.title(),
+            tags: state.tags,
+            output: state.output.clone(),
+        }
+    }
+}
+
+impl UserData for LuaWindow {
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("id", |_, window| Ok(window.id));
+        fields.add_field_method_get("app_id", |_, window| Ok(window.app_id.clone()));
+        fields.add_field_method_get("title", |_, window| Ok(window.title.clone()));
+        fields.add_field_method_get("tags", |_, window| Ok(tag_indices(window.tags)));
+        fields.add_field_method_get("output", |_, window| Ok(window.output.clone()));
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("focus", |lua, window, ()| {
+            queue_action(lua, Action::FocusWindow(window.id));
+            Ok(())
+        });
+        methods.add_method("move_to_tag", |lua, window, tag: Value| {
+            let tags = tag_mask(lua, tag)?;
+            queue_action(
+                lua,
+                Action::MoveWindowToTags {
+                    window: window.id,
+                    tags,
+                },
+            );
+            Ok(())
+        });
+        methods.add_method("close", |lua, window, ()| {
+            queue_action(lua, Action::CloseWindow(window.id));
+            Ok(())
+        });
+    }
+}
+
+/// An output as handed to hooks, with the same snapshot semantics as [`LuaWindow`].
+#[derive(Debug, Clone)]
+pub struct LuaOutput {
+    name: String,
+    width: i32,
+    height: i32,
+    scale: f64,
+    tags: u32,
+}
+
+impl From<&Output> for LuaOutput {
+    fn from(output: &Output) -> Self {
+        let size = output
+            .current_mode()
+            .map(|mode| mode.size)
+            .unwrap_or_default();
+        Self {
+            name: output.name(),
+            width: size.w,
+            height: size.h,
+            scale: output.current_scale().fractional_scale(),
+            tags: visible_tags(output),
+        }
+    }
+}
+
+impl UserData for LuaOutput {
+    fn add_fields<'lua, F: UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("name", |_, output| Ok(output.name.clone()));
+        fields.add_field_method_get("width", |_, output| Ok(output.width));
+        fields.add_field_method_get("height", |_, output| Ok(output.height));
+        fields.add_field_method_get("scale", |_, output| Ok(output.scale));
+        fields.add_field_method_get("tags", |_, output| Ok(tag_indices(output.tags)));
+    }
+
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("view_tag", |lua, output, tag: Value| {
+            let tags = tag_mask(lua, tag)?;
+            queue_action(
+                lua,
+                Action::ViewTags {
+                    tags,
+                    output: Some(output.name.clone()),
+                },
+            );
+            Ok(())
+        });
+        methods.add_method("set_scale", |lua, output, scale: f64| {
+            if !(MIN_SCALE..=MAX_SCALE).contains(&scale) {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "output scale must be between {MIN_SCALE} and {MAX_SCALE}"
+                )));
+            }
+            queue_action(
+                lua,
+                Action::SetOutputScale {
+                    output: output.name.clone(),
+                    scale,
+                },
+            );
+            Ok(())
+        });
+    }
+}
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    /// Call every handler registered for `hook` once the event loop is idle. Hooks fire from
+    /// the middle of commits, focus changes and tag switches, which the actions handlers queue
+    /// would otherwise run into again.
+    pub fn run_hooks<A>(&mut self, hook: Hook, args: A)
+    where
+        A: for<'lua> IntoLuaMulti<'lua> + Clone + 'static,
+    {
+        self.loop_handle
+            .insert_idle(move |data| data.state.call_hooks(hook, args));
+    }
+
+    /// Call the handlers of `hook` right away. Errors are logged and do not keep the other
+    /// handlers from running.
+    fn call_hooks<A>(&mut self, hook: Hook, args: A)
+    where
+        A: for<'lua> IntoLuaMulti<'lua> + Clone,
+    {
+        {
+            let lua = &self.config.lua;
+            let handlers = self.config.with_data(|data| {
+                data.hooks
+                    .get(&hook)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|key| lua.registry_value::<Function>(key).ok())
+                    .collect::<Vec<_>>()
+            });
+            for handler in handlers {
+                if let Err(e) = handler.call::<_, ()>(args.clone()) {
+                    eprintln!("hooks: error in {hook:?} handler: {e}");
+                }
+            }
+        }
+        self.apply_queued_actions();
+    }
+
+    pub fn window_hook(&mut self, hook: Hook, window: &CompostoWindow) {
+        self.run_hooks(hook, LuaWindow::from(window));
+    }
+
+    pub fn output_hook(&mut self, hook: Hook, output: &Output) {
+        self.run_hooks(hook, LuaOutput::from(output));
+    }
+
+    /// Set up a new output, applying its configured scale and telling everyone about it.
+    pub fn output_added(&mut self, output: &Output) {
+        self.emit(Event::OutputAdded {
+            name: output.name(),
+        });
+        self.emit(Event::output_mode(output));
+        self.apply_output_scale(output);
+        self.output_hook(Hook::OutputAdded, output);
+    }
+
+    pub fn output_removed(&mut self, output: &Output) {
+        self.emit(Event::OutputRemoved {
+            name: output.name(),
+        });
+        self.output_hook(Hook::OutputRemoved, output);
+    }
+
+    /// Handle a client going away, sent over from `ClientState::disconnected` which has no access
+    /// to the compositor state.
+    pub fn client_disconnected(&mut self, client: ClientId) {
+        self.clear_selections_of(&client);
+        let pid = self.client_pids.remove(&client);
+        self.run_hooks(Hook::ClientDisconnected, pid);
+    }
+}
@@ -0,0 +1,328 @@
+use smithay::backend::input::{
+    AbsolutePositionEvent, Axis, AxisSource, ButtonState, Event, InputBackend, InputEvent,
+    KeyboardKeyEvent, PointerAxisEvent, PointerButtonEvent, PointerMotionEvent,
+};
+use smithay::desktop::{layer_map_for_output, WindowSurfaceType};
+use smithay::input::pointer::{AxisFrame, ButtonEvent, MotionEvent, RelativeMotionEvent};
+use smithay::output::Output;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::utils::{Logical, Point, Serial, SERIAL_COUNTER};
+use smithay::wayland::compositor::get_parent;
+use smithay::wayland::seat::WaylandFocus;
+use smithay::wayland::shell::wlr_layer::{KeyboardInteractivity, Layer};
+
+use crate::focus::CompostorFocus;
+use crate::render::fullscreen_window;
+use crate::state::{BackendDataExt, Compostate};
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    pub fn process_input_event<I: InputBackend>(&mut self, event: InputEvent<I>) {
+        match event {
+            InputEvent::Keyboard { event } => {
+                let serial = SERIAL_COUNTER.next_serial();
+                let time = Event::time_msec(&event);
+                self.handle_key(event.key_code(), event.state(), serial, time);
+            }
+            InputEvent::PointerMotion { event } => self.on_pointer_motion::<I>(event),
+            InputEvent::PointerMotionAbsolute { event } => {
+                self.on_pointer_motion_absolute::<I>(event)
+            }
+            InputEvent::PointerButton { event } => self.on_pointer_button::<I>(event),
+            InputEvent::PointerAxis { event } => self.on_pointer_axis::<I>(event),
+            _ => {}
+        }
+        // a drag icon or whatever a binding did may need drawing
+        self.schedule_render();
+    }
+
+    fn on_pointer_motion<I: InputBackend>(&mut self, event: I::PointerMotionEvent) {
+        let Some(pointer) = self.seat.get_pointer() else {
+            return;
+        };
+        let location = self.clamp_to_outputs(pointer.current_location() + event.delta());
+        let serial = SERIAL_COUNTER.next_serial();
+        let under = self.surface_under(location);
+
+        pointer.motion(
+            self,
+            under.clone(),
+            &MotionEvent {
+                location,
+                serial,
+                time: event.time_msec(),
+            },
+        );
+        pointer.relative_motion(
+            self,
+            under,
+            &RelativeMotionEvent {
+                delta: event.delta(),
+                delta_unaccel: event.delta_unaccel(),
+                utime: event.time(),
+            },
+        );
+        pointer.frame(self);
+
+        self.pointer_moved(location, serial);
+    }
+
+    /// Absolute motion comes from backends showing a single output in a window, like x11.
+    fn on_pointer_motion_absolute<I: InputBackend>(
+        &mut self,
+        event: I::PointerMotionAbsoluteEvent,
+    ) {
+        let Some(pointer) = self.seat.get_pointer() else {
+            return;
+        };
+        let Some(output_geometry) = self
+            .active_output()
+            .and_then(|output| self.space.output_geometry(&output))
+        else {
+            return;
+        };
+        let location =
+            event.position_transformed(output_geometry.size) + output_geometry.loc.to_f64();
+        let serial = SERIAL_COUNTER.next_serial();
+        let under = self.surface_under(location);
+
+        pointer.motion(
+            self,
+            under,
+            &MotionEvent {
+                location,
+                serial,
+                time: event.time_msec(),
+            },
+        );
+        pointer.frame(self);
+
+        self.pointer_moved(location, serial);
+    }
+
+    fn on_pointer_button<I: InputBackend>(&mut self, event: I::PointerButtonEvent) {
+        let Some(pointer) = self.seat.get_pointer() else {
+            return;
+        };
+        let serial = SERIAL_COUNTER.next_serial();
+        let state = event.state();
+
+        // grabs (like the one of a popup) decide about focus themselves
+        if state == ButtonState::Pressed && !pointer.is_grabbed() {
+            self.focus_under(pointer.current_location(), serial);
+            // the press goes to the grab this starts, not to the client
+            self.modifier_drag(event.button_code(), serial);
+        }
+
+        pointer.button(
+            self,
+            &ButtonEvent {
+                button: event.button_code(),
+                state,
+                serial,
+                time: event.time_msec(),
+            },
+        );
+        pointer.frame(self);
+    }
+
+    fn on_pointer_axis<I: InputBackend>(&mut self, event: I::PointerAxisEvent) {
+        let Some(pointer) = self.seat.get_pointer() else {
+            return;
+        };
+        let source = event.source();
+        let mut frame = AxisFrame::new(event.time_msec()).source(source);
+
+        for axis in [Axis::Horizontal, Axis::Vertical] {
+            // wheels without smooth scrolling only report steps, 15 units each like libinput does
+            let amount = event
+                .amount(axis)
+                .unwrap_or_else(|| event.amount_v120(axis).unwrap_or(0.0) * 15.0 / 120.0);
+            if amount != 0.0 {
+                frame = frame.value(axis, amount);
+                if let Some(v120) = event.amount_v120(axis) {
+                    frame = frame.v120(axis, v120 as i32);
+                }
+            }
+            // lifting the fingers off a touchpad ends kinetic scrolling
+            if source == AxisSource::Finger && event.amount(axis) == Some(0.0) {
+                frame = frame.stop(axis);
+            }
+        }
+
+        pointer.axis(self, frame);
+        pointer.frame(self);
+    }
+
+    /// Keep the pointer inside the area covered by outputs.
+    fn clamp_to_outputs(&self, location: Point<f64, Logical>) -> Point<f64, Logical> {
+        if self.space.output_under(location).next().is_some() {
+            return location;
+        }
+        let Some(area) = self
+            .space
+            .outputs()
+            .filter_map(|output| self.space.output_geometry(output))
+            .reduce(|a, b| a.merge(b))
+        else {
+            return location;
+        };
+        (
+            location
+                .x
+                .clamp(area.loc.x as f64, (area.loc.x + area.size.w - 1) as f64),
+            location
+                .y
+                .clamp(area.loc.y as f64, (area.loc.y + area.size.h - 1) as f64),
+        )
+            .into()
+    }
+
+    /// The focus target under `location` and where its surface is: layer surfaces above windows
+    /// first, then windows (with their popups), then the layer surfaces below them. A fullscreen
+    /// window is only below overlays and hides everything else. Subsurfaces are targets of their
+    /// own, so the pointer goes to the surface it is actually over.
+    pub fn surface_under(
+        &self,
+        location: Point<f64, Logical>,
+    ) -> Option<(CompostorFocus, Point<i32, Logical>)> {
+        let output = self.space.output_under(location).next()?;
+
+        let fullscreen = fullscreen_window(&self.space, output);
+        let above = if fullscreen.is_some() {
+            &[Layer::Overlay][..]
+        } else {
+            &[Layer::Overlay, Layer::Top][..]
+        };
+        if let Some(under) = self.layer_surface_under(output, above, location) {
+            return Some(under);
+        }
+
+        let window = match fullscreen {
+            Some(window) => self
+                .space
+                .element_location(&window)
+                .map(|location| (window.clone(), location - window.0.geometry().loc)),
+            None => self
+                .space
+                .element_under(location)
+                .map(|(window, location)| (window.clone(), location)),
+        };
+        if let Some((window, window_location)) = window {
+            let root = (CompostorFocus::Window(window.clone()), window_location);
+            return Some(
+                window
+                    .0
+                    .surface_under(location - window_location.to_f64(), WindowSurfaceType::ALL)
+                    .map(|(surface, offset)| {
+                        self.target_for(surface, window_location + offset, root.clone())
+                    })
+                    .unwrap_or(root),
+            );
+        }
+
+        self.layer_surface_under(output, &[Layer::Bottom, Layer::Background], location)
+    }
+
+    fn layer_surface_under(
+        &self,
+        output: &Output,
+        layers: &[Layer],
+        location: Point<f64, Logical>,
+    ) -> Option<(CompostorFocus, Point<i32, Logical>)> {
+        let output_location = self.space.output_geometry(output)?.loc;
+        let (layer, layer_location) = {
+            let map = layer_map_for_output(output);
+            layers.iter().find_map(|wanted| {
+                let layer = map.layer_under(*wanted, location - output_location.to_f64())?;
+                Some((
+                    layer.clone(),
+                    map.layer_geometry(layer)?.loc + output_location,
+                ))
+            })?
+        };
+
+        let root = (CompostorFocus::Layer(layer.clone()), layer_location);
+        Some(
+            layer
+                .surface_under(location - layer_location.to_f64(), WindowSurfaceType::ALL)
+                .map(|(surface, offset)| {
+                    self.target_for(surface, layer_location + offset, root.clone())
+                })
+                .unwrap_or(root),
+        )
+    }
+
+    /// Target the popup `surface` belongs to, `root` for its main surface, or else the subsurface
+    /// `surface` is.
+    fn target_for(
+        &self,
+        surface: WlSurface,
+        location: Point<i32, Logical>,
+        root: (CompostorFocus, Point<i32, Logical>),
+    ) -> (CompostorFocus, Point<i32, Logical>) {
+        if let Some(popup) = self.popups.find_popup(&surface) {
+            return (CompostorFocus::Popup(popup), location);
+        }
+        if root.0.wl_surface().as_ref() == Some(&surface) {
+            return root;
+        }
+        (CompostorFocus::Surface(surface), location)
+    }
+
+    /// The window or layer surface a subsurface belongs to.
+    fn subsurface_root(&self, surface: &WlSurface) -> Option<CompostorFocus> {
+        let mut root = surface.clone();
+        while let Some(parent) = get_parent(&root) {
+            root = parent;
+        }
+        self.root_focus(&root)
+    }
+
+    fn pointer_moved(&mut self, location: Point<f64, Logical>, serial: Serial) {
+        let follows_mouse = self.config.with_data(|data| data.focus_follows_mouse);
+        let grabbed = self
+            .seat
+            .get_pointer()
+            .map_or(false, |pointer| pointer.is_grabbed());
+        if follows_mouse && !grabbed {
+            self.focus_under(location, serial);
+        }
+    }
+
+    /// Give the keyboard to the window or layer surface under `location`.
+    fn focus_under(&mut self, location: Point<f64, Logical>, serial: Serial) {
+        let Some(keyboard) = self.seat.get_keyboard() else {
+            return;
+        };
+        // an exclusive layer surface keeps the keyboard for as long as it wants it
+        let current = keyboard.current_focus();
+        if let Some(CompostorFocus::Layer(layer)) = &current {
+            if layer.cached_state().keyboard_interactivity == KeyboardInteractivity::Exclusive {
+                return;
+            }
+        }
+
+        // clicking a subsurface focuses what it is part of
+        let under = match self.surface_under(location) {
+            Some((CompostorFocus::Surface(surface), _)) => self.subsurface_root(&surface),
+            under => under.map(|(target, _)| target),
+        };
+        match under {
+            Some(CompostorFocus::Window(window)) => {
+                if current != Some(CompostorFocus::Window(window.clone())) {
+                    self.focus_window(&window, serial);
+                }
+            }
+            Some(CompostorFocus::Layer(layer)) => {
+                let on_demand =
+                    layer.cached_state().keyboard_interactivity == KeyboardInteractivity::OnDemand;
+                let focus = CompostorFocus::Layer(layer);
+                if on_demand && current != Some(focus.clone()) {
+                    keyboard.set_focus(self, Some(focus), serial);
+                }
+            }
+            // popups without a grab and the empty background leave the focus alone
+            _ => {}
+        }
+    }
+}
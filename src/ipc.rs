@@ -0,0 +1,732 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use smithay::output::Output;
+use smithay::reexports::calloop::{generic::Generic, Interest, LoopHandle, Mode, PostAction};
+
+use crate::state::{BackendDataExt, Compostate, LoopData};
+use crate::tags::MAX_TAGS;
+
+/// Environment variable holding the path of the control socket, set for everything we spawn.
+pub const SOCKET_ENV: &str = "COMPOSTO_SOCKET";
+
+const MSG_USAGE: &str = "usage: composto msg {windows | focus ID | move_to_tag TAG [ID] \
+                         | view_tag TAG [OUTPUT] | spawn CMD | reload | quit \
+                         | subscribe CATEGORY... | JSON}";
+
+/// Longest request line accepted, clients sending more without a newline are dropped.
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+/// Most replies and events kept for a client not reading, before giving up on it.
+const MAX_PENDING: usize = 1024 * 1024;
+
+/// A command sent over the control socket. The protocol is one JSON object per line in each
+/// direction, the command named by its `command` key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Request {
+    /// List every window.
+    Windows,
+    /// Give the keyboard to a window, showing its tag first when it is hidden.
+    Focus {
+        window: u64,
+    },
+    /// Move a window, or the focused one, to a tag.
+    MoveToTag {
+        tag: Tag,
+        window: Option<u64>,
+    },
+    ViewTag {
+        tag: Tag,
+        output: Option<String>,
+    },
+    Spawn {
+        cmd: String,
+    },
+    Reload,
+    Quit,
+    /// Keep the connection open and stream the events of the given categories over it.
+    Subscribe {
+        events: Vec<EventCategory>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventCategory {
+    Window,
+    Tag,
+    Output,
+    Mode,
+    Config,
+}
+
+/// Something that changed in the compositor, sent to the subscribers of its category.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    WindowMapped {
+        id: u64,
+        app_id: Option<String>,
+        title: Option<String>,
+    },
+    WindowUnmapped {
+        id: u64,
+    },
+    /// The keyboard went to another window, or to no window at all.
+    WindowFocused {
+        id: Option<u64>,
+    },
+    WindowTitleChanged {
+        id: u64,
+        title: Option<String>,
+    },
+    WindowAppIdChanged {
+        id: u64,
+        app_id: Option<String>,
+    },
+    /// The tags shown on an output changed.
+    TagsChanged {
+        output: String,
+        tags: Vec<usize>,
+    },
+    OutputAdded {
+        name: String,
+    },
+    OutputRemoved {
+        name: String,
+    },
+    OutputModeChanged {
+        name: String,
+        width: i32,
+        height: i32,
+        refresh: i32,
+        scale: f64,
+    },
+    KeybindingModeChanged {
+        mode: String,
+    },
+    ConfigReloaded,
+    /// The config failed to reload, the previous one is still running.
+    ConfigError {
+        message: String,
+    },
+}
+
+impl Event {
+    pub fn category(&self) -> EventCategory {
+        match self {
+            Event::WindowMapped { .. }
+            | Event::WindowUnmapped { .. }
+            | Event::WindowFocused { .. }
+            | Event::WindowTitleChanged { .. }
+            | Event::WindowAppIdChanged { .. } => EventCategory::Window,
+            Event::TagsChanged { .. } => EventCategory::Tag,
+            Event::OutputAdded { .. }
+            | Event::OutputRemoved { .. }
+            | Event::OutputModeChanged { .. } => EventCategory::Output,
+            Event::KeybindingModeChanged { .. } => EventCategory::Mode,
+            Event::ConfigReloaded | Event::ConfigError { .. } => EventCategory::Config,
+        }
+    }
+
+    /// The mode and scale of `output`, after either changed.
+    pub fn output_mode(output: &Output) -> Self {
+        let mode = output.current_mode();
+        Event::OutputModeChanged {
+            name: output.name(),
+            width: mode.map_or(0, |mode| mode.size.w),
+            height: mode.map_or(0, |mode| mode.size.h),
+            refresh: mode.map_or(0, |mode| mode.refresh),
+            scale: output.current_scale().fractional_scale(),
+        }
+    }
+}
+
+/// The 1-based indices of the tags in `mask`, the way the config counts them.
+pub fn tag_indices(mask: u32) -> Vec<usize> {
+    (0..MAX_TAGS)
+        .filter(|i| mask & (1 << i) != 0)
+        .map(|i| i + 1)
+        .collect()
+}
+
+/// A tag given by its 1-based index or by its name, like in the config.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Tag {
+    Index(usize),
+    Name(String),
+}
+
+impl From<String> for Tag {
+    fn from(s: String) -> Self {
+        match s.parse() {
+            Ok(index) => Tag::Index(index),
+            Err(_) => Tag::Name(s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl Reply {
+    fn ok(data: Option<Value>) -> Self {
+        Self {
+            ok: true,
+            error: None,
+            data,
+        }
+    }
+
+    fn error(error: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            error: Some(error.into()),
+            data: None,
+        }
+    }
+}
+
+/// A client of the control socket. Replies and events go out through the same buffer, so they
+/// reach the client whole and in order.
+struct Connection {
+    id: u64,
+    stream: UnixStream,
+    /// Categories of the events the client subscribed to.
+    events: Vec<EventCategory>,
+    /// Replies and events the socket did not take yet.
+    pending: Vec<u8>,
+    /// Set while the event loop waits for the socket to become writable again.
+    flushing: bool,
+    /// Set once the client stopped sending requests. It is let go when it has all its replies,
+    /// unless it waits for events.
+    hung_up: bool,
+}
+
+impl Connection {
+    /// Write as much of what is pending as the socket takes. Fails when the client went away.
+    fn flush(&mut self) -> std::io::Result<()> {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue `data` behind what is pending and write as much as the socket takes, leaving the
+    /// rest to the event loop. Fails when the client went away or let too much pile up.
+    fn send<BackendData: BackendDataExt + 'static>(
+        &mut self,
+        lh: &LoopHandle<'static, LoopData<BackendData>>,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        self.pending.extend_from_slice(data);
+        self.flush()?;
+        if self.pending.len() > MAX_PENDING {
+            anyhow::bail!("client is not reading");
+        }
+        if !self.pending.is_empty() && !self.flushing {
+            watch_writable(lh, self)?;
+            self.flushing = true;
+        }
+        Ok(())
+    }
+
+    fn done(&self) -> bool {
+        self.hung_up && self.events.is_empty() && self.pending.is_empty()
+    }
+}
+
+/// The control socket. The socket file is removed when the compositor exits.
+pub struct IpcState {
+    pub socket_path: PathBuf,
+    connections: Vec<Connection>,
+    next_connection: u64,
+}
+
+impl Drop for IpcState {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket_path);
+    }
+}
+
+impl IpcState {
+    /// Listen on a socket named after the wayland socket, so several compositors can run side
+    /// by side, and serve it from the event loop.
+    pub fn bind<BackendData: BackendDataExt + 'static>(
+        lh: &LoopHandle<'static, LoopData<BackendData>>,
+        wayland_socket: &str,
+    ) -> anyhow::Result<Self> {
+        let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .ok_or_else(|| anyhow::anyhow!("XDG_RUNTIME_DIR is not set"))?;
+        let socket_path =
+            PathBuf::from(runtime_dir).join(format!("composto-{wayland_socket}.sock"));
+
+        // the wayland socket being free means whatever is left here is stale
+        let _ = std::fs::remove_file(&socket_path);
+        let listener = UnixListener::bind(&socket_path)?;
+        listener.set_nonblocking(true)?;
+
+        lh.insert_source(
+            Generic::new(listener, Interest::READ, Mode::Level),
+            |_, listener, data| {
+                loop {
+                    match listener.as_ref().accept() {
+                        Ok((stream, _)) => {
+                            let Some(ipc) = &mut data.state.ipc else {
+                                break;
+                            };
+                            if let Err(e) = ipc.accept(&data.state.loop_handle, stream) {
+                                eprintln!("ipc: unable to accept a client: {e}");
+                            }
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                        Err(e) => {
+                            eprintln!("ipc: error accepting a client: {e}");
+                            break;
+                        }
+                    }
+                }
+                Ok(PostAction::Continue)
+            },
+        )
+        .map_err(|e| anyhow::anyhow!("{}", e.error))?;
+
+        println!("ipc: listening on {}", socket_path.display());
+        std::env::set_var(SOCKET_ENV, &socket_path);
+        Ok(Self {
+            socket_path,
+            connections: Vec::new(),
+            next_connection: 0,
+        })
+    }
+
+    /// Serve the requests of a new client from the event loop.
+    fn accept<BackendData: BackendDataExt + 'static>(
+        &mut self,
+        lh: &LoopHandle<'static, LoopData<BackendData>>,
+        stream: UnixStream,
+    ) -> anyhow::Result<()> {
+        stream.set_nonblocking(true)?;
+        self.next_connection += 1;
+        let id = self.next_connection;
+        let reader = stream.try_clone()?;
+        let mut buffer = Vec::new();
+
+        lh.insert_source(
+            Generic::new(reader, Interest::READ, Mode::Level),
+            move |_, stream, data| {
+                let mut stream: &UnixStream = stream.as_ref();
+                let mut chunk = [0; 4096];
+                loop {
+                    match stream.read(&mut chunk) {
+                        Ok(0) => {
+                            data.state.hang_up(id);
+                            return Ok(PostAction::Remove);
+                        }
+                        Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                            return Ok(PostAction::Continue)
+                        }
+                        Err(_) => {
+                            data.state.drop_connection(id);
+                            return Ok(PostAction::Remove);
+                        }
+                    }
+
+                    while let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                        let line = buffer.drain(..=end).collect::<Vec<_>>();
+                        let reply = match serde_json::from_slice::<Request>(&line) {
+                            Ok(request) => data.state.handle_request(request, id),
+                            Err(e) => Reply::error(format!("invalid request: {e}")),
+                        };
+                        if !data.state.send_reply(id, &reply) {
+                            return Ok(PostAction::Remove);
+                        }
+                    }
+
+                    // what is left is the start of a request, and no request is that long
+                    if buffer.len() > MAX_REQUEST_LEN {
+                        data.state.send_reply(id, &Reply::error("request too long"));
+                        data.state.hang_up(id);
+                        return Ok(PostAction::Remove);
+                    }
+                }
+            },
+        )
+        .map_err(|e| anyhow::anyhow!("{}", e.error))?;
+
+        self.connections.push(Connection {
+            id,
+            stream,
+            events: Vec::new(),
+            pending: Vec::new(),
+            flushing: false,
+            hung_up: false,
+        });
+        Ok(())
+    }
+
+    /// Write what is pending for the connection `id` once its socket is writable again, for as
+    /// long as something is left.
+    fn flush_connection(&mut self, id: u64) -> PostAction {
+        let Some(i) = self.connections.iter().position(|c| c.id == id) else {
+            return PostAction::Remove;
+        };
+        let connection = &mut self.connections[i];
+        if connection.flush().is_err() {
+            self.connections.remove(i);
+            return PostAction::Remove;
+        }
+        if !connection.pending.is_empty() {
+            return PostAction::Continue;
+        }
+        connection.flushing = false;
+        if connection.done() {
+            self.connections.remove(i);
+        }
+        PostAction::Remove
+    }
+}
+
+/// Have the event loop flush `connection` when its socket can take more.
+fn watch_writable<BackendData: BackendDataExt + 'static>(
+    lh: &LoopHandle<'static, LoopData<BackendData>>,
+    connection: &Connection,
+) -> anyhow::Result<()> {
+    let id = connection.id;
+    lh.insert_source(
+        Generic::new(connection.stream.try_clone()?, Interest::WRITE, Mode::Level),
+        move |_, _, data| match &mut data.state.ipc {
+            Some(ipc) => Ok(ipc.flush_connection(id)),
+            None => Ok(PostAction::Remove),
+        },
+    )
+    .map_err(|e| anyhow::anyhow!("{}", e.error))?;
+    Ok(())
+}
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    fn handle_request(&mut self, request: Request, id: u64) -> Reply {
+        match request {
+            Request::Windows => return Reply::ok(Some(self.windows_json())),
+            Request::Focus { window } => {
+                let Some(window) = self.window_by_id(window) else {
+                    return Reply::error(format!("no window with id {window}"));
+                };
+                self.raise_window(&window);
+            }
+            Request::MoveToTag { tag, window } => {
+                let tags = match self.tag_bit(&tag) {
+                    Ok(tags) => tags,
+                    Err(e) => return Reply::error(e),
+                };
+                let window = match window {
+                    Some(id) => match self.window_by_id(id) {
+                        Some(window) => window,
+                        None => return Reply::error(format!("no window with id {id}")),
+                    },
+                    None => match self.focused_window() {
+                        Some(window) => window,
+                        None => return Reply::error("no window is focused"),
+                    },
+                };
+                self.move_window_to_tags(&window, tags);
+            }
+            Request::ViewTag { tag, output } => {
+                let tags = match self.tag_bit(&tag) {
+                    Ok(tags) => tags,
+                    Err(e) => return Reply::error(e),
+                };
+                if let Some(name) = &output {
+                    if self.output_by_name(name).is_none() {
+                        return Reply::error(format!("no output named {name}"));
+                    }
+                }
+                self.view_tags(tags, output);
+            }
+            Request::Spawn { cmd } => self.spawn(&cmd),
+            Request::Reload => {
+                if let Err(e) = self.reload_config() {
+                    return Reply::error(e.to_string());
+                }
+            }
+            Request::Quit => self
+                .running
+                .store(false, std::sync::atomic::Ordering::SeqCst),
+            Request::Subscribe { events } => {
+                let Some(connection) = self
+                    .ipc
+                    .as_mut()
+                    .and_then(|ipc| ipc.connections.iter_mut().find(|c| c.id == id))
+                else {
+                    return Reply::error("unable to subscribe");
+                };
+                connection.events.extend(events);
+            }
+        }
+        Reply::ok(None)
+    }
+
+    /// Send `reply` to the connection `id`, behind whatever it is still waiting for. Returns
+    /// whether the connection is still there.
+    fn send_reply(&mut self, id: u64, reply: &Reply) -> bool {
+        let Some(ipc) = &mut self.ipc else {
+            return false;
+        };
+        let Some(i) = ipc.connections.iter().position(|c| c.id == id) else {
+            return false;
+        };
+        let mut line = serde_json::to_vec(reply).expect("replies always serialize");
+        line.push(b'\n');
+        if ipc.connections[i].send(&self.loop_handle, &line).is_err() {
+            ipc.connections.remove(i);
+            return false;
+        }
+        true
+    }
+
+    /// The client `id` will not send any more requests.
+    fn hang_up(&mut self, id: u64) {
+        if let Some(ipc) = &mut self.ipc {
+            for connection in &mut ipc.connections {
+                if connection.id == id {
+                    connection.hung_up = true;
+                }
+            }
+            ipc.connections.retain(|c| !c.done());
+        }
+    }
+
+    fn drop_connection(&mut self, id: u64) {
+        if let Some(ipc) = &mut self.ipc {
+            ipc.connections.retain(|c| c.id != id);
+        }
+    }
+
+    /// Send `event` to everyone subscribed to its category. What a socket does not take right
+    /// away is sent once it is writable again; subscribers that went away, or let too much pile
+    /// up, are dropped.
+    pub fn emit(&mut self, event: Event) {
+        let Some(ipc) = &mut self.ipc else {
+            return;
+        };
+        let category = event.category();
+        if !ipc
+            .connections
+            .iter()
+            .any(|connection| connection.events.contains(&category))
+        {
+            return;
+        }
+
+        let mut line = serde_json::to_vec(&event).expect("events always serialize");
+        line.push(b'\n');
+        let loop_handle = &self.loop_handle;
+        ipc.connections.retain_mut(|connection| {
+            !connection.events.contains(&category) || connection.send(loop_handle, &line).is_ok()
+        });
+    }
+
+    pub fn emit_keybinding_mode(&mut self) {
+        let mode = self.keybindings.mode().to_string();
+        self.emit(Event::KeybindingModeChanged { mode });
+    }
+
+    fn tag_bit(&self, tag: &Tag) -> Result<u32, String> {
+        let index = match tag {
+            Tag::Index(i) if (1..=MAX_TAGS).contains(i) => i - 1,
+            Tag::Index(i) => return Err(format!("invalid tag {i}")),
+            Tag::Name(name) => self
+                .config
+                .with_data(|data| data.tags.iter().position(|tag| tag == name))
+                .ok_or_else(|| format!("unknown tag `{name}`"))?,
+        };
+        Ok(1 << index)
+    }
+
+    fn windows_json(&self) -> Value {
+        let focused = self.focused_window();
+        let windows = self
+            .windows
+            .iter()
+            .map(|window| {
+                let state = window.state();
+                json!({
+                    "id": state.id,
+                    "app_id": window.app_id(),
+                    "title": window.title(),
+                    "tags": tag_indices(state.tags),
+                    "output": state.output,
+                    "floating": state.floating,
+                    "maximized": state.maximized,
+                    "fullscreen": state.fullscreen,
+                    "visible": self.space.element_location(window).is_some(),
+                    "focused": focused.as_ref() == Some(window),
+                })
+            })
+            .collect::<Vec<_>>();
+        Value::Array(windows)
+    }
+}
+
+/// Build the request for `composto msg` from its arguments, either a command with its
+/// arguments or a raw JSON request.
+fn request_from_args(args: &[String]) -> Option<Request> {
+    let (command, args) = args.split_first()?;
+    if command.starts_with('{') {
+        return serde_json::from_str(command).ok();
+    }
+
+    let arg = |i: usize| args.get(i).cloned();
+    let request = match (command.as_str(), args.len()) {
+        ("windows", 0) => Request::Windows,
+        ("focus", 1) => Request::Focus {
+            window: arg(0)?.parse().ok()?,
+        },
+        ("move_to_tag", 1 | 2) => Request::MoveToTag {
+            tag: arg(0)?.into(),
+            window: match arg(1) {
+                Some(id) => Some(id.parse().ok()?),
+                None => None,
+            },
+        },
+        ("view_tag", 1 | 2) => Request::ViewTag {
+            tag: arg(0)?.into(),
+            output: arg(1),
+        },
+        ("spawn", 1..) => Request::Spawn {
+            cmd: args.join(" "),
+        },
+        ("reload", 0) => Request::Reload,
+        ("quit", 0) => Request::Quit,
+        ("subscribe", 1..) => Request::Subscribe {
+            events: args
+                .iter()
+                .map(|event| serde_json::from_value(Value::String(event.clone())).ok())
+                .collect::<Option<_>>()?,
+        },
+        _ => return None,
+    };
+    Some(request)
+}
+
+/// `composto msg`: send one request to the running compositor and print its reply. Fails when
+/// the compositor reports an error, so scripts can check the exit status.
+pub fn run_msg(args: &[String]) -> anyhow::Result<()> {
+    let Some(request) = request_from_args(args) else {
+        anyhow::bail!("{MSG_USAGE}");
+    };
+    let socket_path = std::env::var_os(SOCKET_ENV)
+        .ok_or_else(|| anyhow::anyhow!("{SOCKET_ENV} is not set, is composto running?"))?;
+
+    let mut stream = UnixStream::connect(&socket_path)?;
+    let mut line = serde_json::to_vec(&request)?;
+    line.push(b'\n');
+    stream.write_all(&line)?;
+
+    let mut lines = BufReader::new(stream).lines();
+    let reply = lines
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("composto closed the connection"))??;
+    println!("{reply}");
+
+    let reply: Reply = serde_json::from_str(&reply)?;
+    if !reply.ok {
+        anyhow::bail!("{}", reply.error.unwrap_or_default());
+    }
+
+    // subscriptions print events until composto goes away
+    if matches!(request, Request::Subscribe { .. }) {
+        for event in lines {
+            println!("{}", event?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(args: &[&str]) -> Option<Request> {
+        let args = args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        request_from_args(&args)
+    }
+
+    #[test]
+    fn commands_with_arguments() {
+        assert_eq!(request(&["windows"]), Some(Request::Windows));
+        assert_eq!(request(&["focus", "3"]), Some(Request::Focus { window: 3 }));
+        assert_eq!(
+            request(&["move_to_tag", "2"]),
+            Some(Request::MoveToTag {
+                tag: Tag::Index(2),
+                window: None,
+            })
+        );
+        assert_eq!(
+            request(&["move_to_tag", "web", "5"]),
+            Some(Request::MoveToTag {
+                tag: Tag::Name("web".into()),
+                window: Some(5),
+            })
+        );
+        assert_eq!(
+            request(&["view_tag", "1", "HDMI-A-1"]),
+            Some(Request::ViewTag {
+                tag: Tag::Index(1),
+                output: Some("HDMI-A-1".into()),
+            })
+        );
+        assert_eq!(
+            request(&["spawn", "foot", "-e", "htop"]),
+            Some(Request::Spawn {
+                cmd: "foot -e htop".into(),
+            })
+        );
+        assert_eq!(
+            request(&["subscribe", "window", "tag"]),
+            Some(Request::Subscribe {
+                events: vec![EventCategory::Window, EventCategory::Tag],
+            })
+        );
+    }
+
+    #[test]
+    fn raw_json_requests() {
+        assert_eq!(request(&[r#"{"command":"quit"}"#]), Some(Request::Quit));
+        assert_eq!(
+            request(&[r#"{"command":"focus","window":7}"#]),
+            Some(Request::Focus { window: 7 })
+        );
+        assert_eq!(request(&[r#"{"command":"nope"}"#]), None);
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert_eq!(request(&[]), None);
+        assert_eq!(request(&["windows", "extra"]), None);
+        assert_eq!(request(&["focus", "x"]), None);
+        assert_eq!(request(&["move_to_tag", "1", "x"]), None);
+        assert_eq!(request(&["subscribe", "bogus"]), None);
+        assert_eq!(request(&["frobnicate"]), None);
+    }
+}
@@ -0,0 +1,420 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use mlua::{Function, RegistryKey};
+use smithay::backend::input::KeyState;
+use smithay::input::keyboard::{xkb, FilterResult, KeysymHandle, ModifiersState};
+
+use crate::state::{BackendDataExt, Compostate};
+
+pub const DEFAULT_MODE: &str = "default";
+pub const DEFAULT_CHORD_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    pub logo: bool,
+}
+
+impl From<&ModifiersState> for Modifiers {
+    fn from(state: &ModifiersState) -> Self {
+        Self {
+            ctrl: state.ctrl,
+            alt: state.alt,
+            shift: state.shift,
+            logo: state.logo,
+        }
+    }
+}
+
+impl Modifiers {
+    /// Parse modifiers joined by `+`, like `Super+Shift`.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut modifiers = Self::default();
+        for modifier in s.split('+') {
+            modifiers.add(modifier, s)?;
+        }
+        Ok(modifiers)
+    }
+
+    fn add(&mut self, modifier: &str, s: &str) -> Result<(), String> {
+        match modifier.to_lowercase().as_str() {
+            "super" | "logo" | "mod4" => self.logo = true,
+            "ctrl" | "control" => self.ctrl = true,
+            "alt" | "mod1" => self.alt = true,
+            "shift" => self.shift = true,
+            _ => return Err(format!("unknown modifier `{modifier}` in `{s}`")),
+        }
+        Ok(())
+    }
+}
+
+/// A single key press with the modifiers that have to be held for it, e.g. `Super+Shift+q`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyCombo {
+    pub modifiers: Modifiers,
+    pub keysym: xkb::Keysym,
+}
+
+impl KeyCombo {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut modifiers = Modifiers::default();
+        let mut parts = s.split('+').collect::<Vec<_>>();
+        let key = parts
+            .pop()
+            .filter(|key| !key.is_empty())
+            .ok_or_else(|| format!("missing key in `{s}`"))?;
+
+        for modifier in parts {
+            modifiers.add(modifier, s)?;
+        }
+
+        // single letters are matched against the unshifted keysym, so `Super+Q` and `Super+q`
+        // mean the same thing
+        let key = if key.chars().count() == 1 {
+            key.to_lowercase()
+        } else {
+            key.to_string()
+        };
+        let mut keysym = xkb::keysym_from_name(&key, xkb::KEYSYM_NO_FLAGS);
+        if keysym.raw() == xkb::keysyms::KEY_NoSymbol {
+            keysym = xkb::keysym_from_name(&key, xkb::KEYSYM_CASE_INSENSITIVE);
+        }
+        if keysym.raw() == xkb::keysyms::KEY_NoSymbol {
+            return Err(format!("unknown key `{key}` in `{s}`"));
+        }
+
+        Ok(Self { modifiers, keysym })
+    }
+
+    fn matches(&self, modifiers: Modifiers, handle: &KeysymHandle<'_>) -> bool {
+        self.modifiers == modifiers
+            && (handle.raw_syms().contains(&self.keysym) || handle.modified_sym() == self.keysym)
+    }
+}
+
+/// Parse a binding like `Super+x Super+c`: a sequence of combos that have to be pressed one after
+/// the other.
+pub fn parse_sequence(s: &str) -> Result<Vec<KeyCombo>, String> {
+    let sequence = s
+        .split_whitespace()
+        .map(KeyCombo::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    if sequence.is_empty() {
+        return Err("empty keybinding".into());
+    }
+    Ok(sequence)
+}
+
+pub struct Keybinding {
+    pub sequence: Vec<KeyCombo>,
+    pub mode: String,
+    /// Fire when the last key of the sequence is released rather than when it is pressed.
+    pub on_release: bool,
+    pub callback: RegistryKey,
+}
+
+/// Runtime state of the keybinding engine.
+pub struct KeybindingState {
+    mode: String,
+    /// Combos of a chord pressed so far.
+    pending: Vec<KeyCombo>,
+    deadline: Option<Instant>,
+    /// Keys whose press was consumed by a binding, so their release is not sent to clients either.
+    suppressed: HashSet<u32>,
+    /// Keys whose release fires a binding, together with the index of that binding.
+    release: Vec<(u32, usize)>,
+}
+
+impl Default for KeybindingState {
+    fn default() -> Self {
+        Self {
+            mode: DEFAULT_MODE.into(),
+            pending: Vec::new(),
+            deadline: None,
+            suppressed: HashSet::new(),
+            release: Vec::new(),
+        }
+    }
+}
+
+impl KeybindingState {
+    pub fn enter_mode(&mut self, mode: String) {
+        println!("keybindings: entering mode {mode}");
+        self.mode = mode;
+        self.pending.clear();
+        self.deadline = None;
+    }
+
+    pub fn exit_mode(&mut self) {
+        self.enter_mode(DEFAULT_MODE.into());
+    }
+
+    pub fn mode(&self) -> &str {
+        &self.mode
+    }
+
+    /// Forget everything referring to the current bindings, which are about to be replaced.
+    /// Keys already held stay suppressed so clients never see only half of a press.
+    pub fn reset(&mut self) {
+        self.mode = DEFAULT_MODE.into();
+        self.pending.clear();
+        self.deadline = None;
+        self.release.clear();
+    }
+}
+
+enum KeyAction {
+    Forward,
+    Intercept,
+    Fire(usize),
+    SwitchVt(i32),
+}
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    /// Match a key event against the configured bindings, forwarding it to the focused client
+    /// when no binding takes it.
+    pub fn handle_key(
+        &mut self,
+        keycode: u32,
+        key_state: KeyState,
+        serial: smithay::utils::Serial,
+        time: u32,
+    ) {
+        let Some(keyboard) = self.seat.get_keyboard() else {
+            return;
+        };
+
+        let fired = keyboard.input(
+            self,
+            keycode,
+            key_state,
+            serial,
+            time,
+            |state, modifiers, handle| match state.match_key(keycode, key_state, modifiers, &handle)
+            {
+                KeyAction::Forward => FilterResult::Forward,
+                KeyAction::Intercept => FilterResult::Intercept(None),
+                KeyAction::Fire(binding) => FilterResult::Intercept(Some(binding)),
+                KeyAction::SwitchVt(vt) => {
+                    state.backend_data.change_vt(vt);
+                    FilterResult::Intercept(None)
+                }
+            },
+        );
+
+        if let Some(Some(binding)) = fired {
+            self.fire_binding(binding);
+        }
+    }
+
+    fn match_key(
+        &mut self,
+        keycode: u32,
+        key_state: KeyState,
+        modifiers: &ModifiersState,
+        handle: &KeysymHandle<'_>,
+    ) -> KeyAction {
+        let bindings = &mut self.keybindings;
+
+        if key_state == KeyState::Released {
+            if let Some(i) = bindings
+                .release
+                .iter()
+                .position(|(code, _)| *code == keycode)
+            {
+                let (_, binding) = bindings.release.remove(i);
+                bindings.suppressed.remove(&keycode);
+                return KeyAction::Fire(binding);
+            }
+            if bindings.suppressed.remove(&keycode) {
+                return KeyAction::Intercept;
+            }
+            return KeyAction::Forward;
+        }
+
+        // the way out to another vt stays open whatever the bindings and the focused client are
+        let keysym = handle.modified_sym().raw();
+        if (xkb::keysyms::KEY_XF86Switch_VT_1..=xkb::keysyms::KEY_XF86Switch_VT_12)
+            .contains(&keysym)
+        {
+            bindings.suppressed.insert(keycode);
+            return KeyAction::SwitchVt((keysym - xkb::keysyms::KEY_XF86Switch_VT_1 + 1) as i32);
+        }
+
+        // pressing a modifier must not break a chord, it is part of the next combo
+        if handle.modified_sym().is_modifier_key() {
+            return KeyAction::Forward;
+        }
+
+        if bindings
+            .deadline
+            .map_or(false, |deadline| Instant::now() > deadline)
+        {
+            bindings.pending.clear();
+            bindings.deadline = None;
+        }
+
+        let modifiers = Modifiers::from(modifiers);
+        let step = bindings.pending.len();
+        let pending = &bindings.pending;
+        let mode = &bindings.mode;
+
+        let (exact, longer) = self.config.with_data(|data| {
+            let candidates = data.keybindings.iter().enumerate().filter(|(_, binding)| {
+                &binding.mode == mode
+                    && binding.sequence.len() > step
+                    && binding.sequence[..step] == pending[..]
+                    && binding.sequence[step].matches(modifiers, handle)
+            });
+
+            let mut exact = None;
+            let mut longer = false;
+            for (i, binding) in candidates {
+                if binding.sequence.len() == step + 1 {
+                    exact = exact.or(Some((i, binding.on_release)));
+                } else {
+                    longer = true;
+                }
+            }
+            (exact, longer)
+        });
+
+        let chord_timeout = self.config.with_data(|data| data.chord_timeout);
+        let bindings = &mut self.keybindings;
+        let in_chord = !bindings.pending.is_empty();
+
+        if let Some((binding, on_release)) = exact {
+            bindings.pending.clear();
+            bindings.deadline = None;
+            bindings.suppressed.insert(keycode);
+            if on_release {
+                bindings.release.push((keycode, binding));
+                return KeyAction::Intercept;
+            }
+            return KeyAction::Fire(binding);
+        }
+
+        if longer {
+            let combo = KeyCombo {
+                modifiers,
+                keysym: handle.modified_sym(),
+            };
+            // remember the combo as it was written in the binding, so the prefix comparison of the
+            // next step works no matter which of the keysyms matched
+            let combo = self
+                .config
+                .with_data(|data| {
+                    data.keybindings
+                        .iter()
+                        .filter(|binding| binding.sequence.len() > step + 1)
+                        .map(|binding| binding.sequence[step])
+                        .find(|candidate| candidate.matches(modifiers, handle))
+                })
+                .unwrap_or(combo);
+
+            let bindings = &mut self.keybindings;
+            bindings.pending.push(combo);
+            bindings.deadline = Some(Instant::now() + chord_timeout);
+            bindings.suppressed.insert(keycode);
+            return KeyAction::Intercept;
+        }
+
+        if in_chord {
+            // a key that does not continue the chord aborts it, and is swallowed
+            bindings.pending.clear();
+            bindings.deadline = None;
+            bindings.suppressed.insert(keycode);
+            return KeyAction::Intercept;
+        }
+
+        KeyAction::Forward
+    }
+
+    fn fire_binding(&mut self, binding: usize) {
+        let callback = self.config.with_data(|data| {
+            data.keybindings.get(binding).and_then(|binding| {
+                self.config
+                    .lua
+                    .registry_value::<Function>(&binding.callback)
+                    .ok()
+            })
+        });
+
+        if let Some(callback) = callback {
+            if let Err(e) = callback.call::<_, ()>(()) {
+                eprintln!("keybindings: error in binding callback: {e}");
+            }
+        }
+
+        self.apply_queued_actions();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_modifiers() {
+        assert_eq!(
+            Modifiers::parse("Super+Shift"),
+            Ok(Modifiers {
+                logo: true,
+                shift: true,
+                ..Default::default()
+            })
+        );
+        assert_eq!(
+            Modifiers::parse("ctrl+Mod1"),
+            Ok(Modifiers {
+                ctrl: true,
+                alt: true,
+                ..Default::default()
+            })
+        );
+        assert!(Modifiers::parse("Super+Hyper").is_err());
+        assert!(Modifiers::parse("").is_err());
+    }
+
+    #[test]
+    fn parse_combo() {
+        let combo = KeyCombo::parse("Ctrl+Alt+Return").unwrap();
+        assert_eq!(
+            combo.modifiers,
+            Modifiers {
+                ctrl: true,
+                alt: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(combo.keysym.raw(), xkb::keysyms::KEY_Return);
+    }
+
+    #[test]
+    fn single_letters_ignore_case() {
+        let upper = KeyCombo::parse("Super+Q").unwrap();
+        assert_eq!(upper, KeyCombo::parse("Super+q").unwrap());
+        assert_eq!(upper.keysym.raw(), xkb::keysyms::KEY_q);
+    }
+
+    #[test]
+    fn parse_chord() {
+        let sequence = parse_sequence("Super+x  Super+c").unwrap();
+        assert_eq!(sequence.len(), 2);
+        assert_eq!(sequence[0].keysym.raw(), xkb::keysyms::KEY_x);
+        assert_eq!(sequence[1].keysym.raw(), xkb::keysyms::KEY_c);
+        assert!(sequence.iter().all(|combo| combo.modifiers.logo));
+    }
+
+    #[test]
+    fn parse_invalid_bindings() {
+        assert!(parse_sequence("").is_err());
+        assert!(parse_sequence("   ").is_err());
+        assert!(parse_sequence("Super+").is_err());
+        assert!(parse_sequence("Hyper+a").is_err());
+        assert!(parse_sequence("Super+NotAKey").is_err());
+    }
+}
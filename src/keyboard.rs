@@ -0,0 +1,110 @@
+use mlua::Table;
+use smithay::input::keyboard::{Layout as KeyboardLayout, XkbConfig};
+
+use crate::state::{BackendDataExt, Compostate};
+
+pub const DEFAULT_REPEAT_DELAY: i32 = 200;
+pub const DEFAULT_REPEAT_RATE: i32 = 25;
+
+/// xkb settings of the keyboard of a seat. Empty strings leave the choice to xkb.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardConfig {
+    /// Seat the settings are for, or `None` for every seat without settings of its own.
+    pub seat: Option<String>,
+    pub rules: String,
+    pub model: String,
+    /// Comma separated layouts, switched between with `composto.switch_keyboard_layout`.
+    pub layout: String,
+    pub variant: String,
+    pub options: Option<String>,
+    /// Milliseconds before a held key starts repeating.
+    pub repeat_delay: i32,
+    /// Repeats per second.
+    pub repeat_rate: i32,
+}
+
+impl Default for KeyboardConfig {
+    fn default() -> Self {
+        Self {
+            seat: None,
+            rules: String::new(),
+            model: String::new(),
+            layout: String::new(),
+            variant: String::new(),
+            options: None,
+            repeat_delay: DEFAULT_REPEAT_DELAY,
+            repeat_rate: DEFAULT_REPEAT_RATE,
+        }
+    }
+}
+
+impl KeyboardConfig {
+    /// Read the settings given to `composto.keyboard`, using the defaults for missing keys.
+    pub fn from_table(table: Table<'_>) -> mlua::Result<Self> {
+        let string = |key: &str| -> mlua::Result<String> {
+            Ok(table.get::<_, Option<String>>(key)?.unwrap_or_default())
+        };
+        Ok(Self {
+            seat: table.get("seat")?,
+            rules: string("rules")?,
+            model: string("model")?,
+            layout: string("layout")?,
+            variant: string("variant")?,
+            options: table.get("options")?,
+            repeat_delay: table
+                .get::<_, Option<i32>>("repeat_delay")?
+                .unwrap_or(DEFAULT_REPEAT_DELAY),
+            repeat_rate: table
+                .get::<_, Option<i32>>("repeat_rate")?
+                .unwrap_or(DEFAULT_REPEAT_RATE),
+        })
+    }
+
+    pub fn xkb_config(&self) -> XkbConfig<'_> {
+        XkbConfig {
+            rules: &self.rules,
+            model: &self.model,
+            layout: &self.layout,
+            variant: &self.variant,
+            options: self.options.clone(),
+        }
+    }
+}
+
+/// The settings for the seat named `seat`, preferring ones given for it by name.
+pub fn keyboard_config(configs: &[KeyboardConfig], seat: &str) -> KeyboardConfig {
+    configs
+        .iter()
+        .rev()
+        .find(|config| config.seat.as_deref() == Some(seat))
+        .or_else(|| configs.iter().rev().find(|config| config.seat.is_none()))
+        .cloned()
+        .unwrap_or_default()
+}
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    /// Set up the keyboard of the seat from the config again, after it was reloaded.
+    pub fn apply_keyboard_config(&mut self) {
+        let Some(keyboard) = self.seat.get_keyboard() else {
+            return;
+        };
+        let config = self
+            .config
+            .with_data(|data| keyboard_config(&data.keyboards, self.seat.name()));
+        if let Err(e) = keyboard.set_xkb_config(self, config.xkb_config()) {
+            eprintln!("keyboard: invalid xkb settings, keeping the current keymap: {e}");
+        }
+        keyboard.change_repeat_info(config.repeat_rate, config.repeat_delay);
+    }
+
+    /// Switch to the xkb layout at `index`, or to the next one.
+    pub fn switch_keyboard_layout(&mut self, index: Option<u32>) {
+        let Some(keyboard) = self.seat.get_keyboard() else {
+            return;
+        };
+        keyboard.with_xkb_state(self, |mut context| match index {
+            Some(index) => context.set_layout(KeyboardLayout(index)),
+            None => context.cycle_next_layout(),
+        });
+    }
+}
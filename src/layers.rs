@@ -0,0 +1,173 @@
+use smithay::delegate_layer_shell;
+use smithay::desktop::{layer_map_for_output, LayerSurface, WindowSurfaceType};
+use smithay::output::Output;
+use smithay::reexports::wayland_server::protocol::{wl_output::WlOutput, wl_surface::WlSurface};
+use smithay::utils::SERIAL_COUNTER;
+use smithay::wayland::compositor::with_states;
+use smithay::wayland::shell::wlr_layer::{
+    KeyboardInteractivity, Layer, LayerSurface as WlrLayerSurface, LayerSurfaceData,
+    WlrLayerShellHandler, WlrLayerShellState,
+};
+
+use crate::focus::CompostorFocus;
+use crate::state::{BackendDataExt, Compostate};
+
+impl<BackendData: BackendDataExt + 'static> WlrLayerShellHandler for Compostate<BackendData> {
+    fn shell_state(&mut self) -> &mut WlrLayerShellState {
+        &mut self.layer_shell_state
+    }
+
+    fn new_layer_surface(
+        &mut self,
+        surface: WlrLayerSurface,
+        wl_output: Option<WlOutput>,
+        _layer: Layer,
+        namespace: String,
+    ) {
+        let Some(output) = wl_output
+            .as_ref()
+            .and_then(Output::from_resource)
+            .or_else(|| self.active_output())
+        else {
+            // nowhere to show it
+            surface.send_close();
+            return;
+        };
+
+        let layer = LayerSurface::new(surface, namespace);
+        if let Err(e) = layer_map_for_output(&output).map_layer(&layer) {
+            eprintln!("layer shell: unable to map layer surface: {e}");
+        }
+    }
+
+    fn layer_destroyed(&mut self, surface: WlrLayerSurface) {
+        let Some(output) = self.layer_output(surface.wl_surface()) else {
+            return;
+        };
+
+        {
+            let mut map = layer_map_for_output(&output);
+            let layer = map
+                .layer_for_surface(surface.wl_surface(), WindowSurfaceType::TOPLEVEL)
+                .cloned();
+            if let Some(layer) = layer {
+                map.unmap_layer(&layer);
+            }
+        }
+
+        self.arrange_output(&output);
+        self.update_layer_focus();
+    }
+}
+
+delegate_layer_shell!(@<BackendData: BackendDataExt + 'static> Compostate<BackendData>);
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    /// Every layer surface of every output.
+    pub fn layer_surfaces(&self) -> Vec<LayerSurface> {
+        self.space
+            .outputs()
+            .flat_map(|output| {
+                layer_map_for_output(output)
+                    .layers()
+                    .cloned()
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    pub fn layer_output(&self, surface: &WlSurface) -> Option<Output> {
+        self.space
+            .outputs()
+            .find(|output| {
+                layer_map_for_output(output)
+                    .layer_for_surface(surface, WindowSurfaceType::TOPLEVEL)
+                    .is_some()
+            })
+            .cloned()
+    }
+
+    /// Handle a commit of a layer surface, returning whether `surface` was one.
+    pub fn layer_commit(&mut self, surface: &WlSurface) -> bool {
+        let Some(output) = self.layer_output(surface) else {
+            return false;
+        };
+
+        let initial_configure_sent = with_states(surface, |states| {
+            states
+                .data_map
+                .get::<LayerSurfaceData>()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .initial_configure_sent
+        });
+
+        let zone_changed = {
+            let mut map = layer_map_for_output(&output);
+            let zone = map.non_exclusive_zone();
+            // a commit may change the anchor, size or exclusive zone of the layer
+            map.arrange();
+            if !initial_configure_sent {
+                if let Some(layer) = map.layer_for_surface(surface, WindowSurfaceType::TOPLEVEL) {
+                    layer.layer_surface().send_configure();
+                }
+            }
+            map.non_exclusive_zone() != zone
+        };
+
+        // windows only care about the space panels and docks leave them
+        if zone_changed {
+            self.arrange_output(&output);
+        } else {
+            self.update_surface_scales(&output);
+        }
+        self.update_layer_focus();
+        true
+    }
+
+    /// Give the keyboard to the topmost layer surface asking for exclusive keyboard interactivity,
+    /// or take it away from a layer surface that no longer gets it.
+    pub fn update_layer_focus(&mut self) {
+        let Some(keyboard) = self.seat.get_keyboard() else {
+            return;
+        };
+
+        let layers = self.layer_surfaces();
+        let exclusive = [Layer::Overlay, Layer::Top].into_iter().find_map(|wanted| {
+            layers.iter().find(|layer| {
+                let state = layer.cached_state();
+                state.layer == wanted
+                    && state.keyboard_interactivity == KeyboardInteractivity::Exclusive
+            })
+        });
+
+        let current = keyboard.current_focus();
+        match exclusive {
+            Some(layer) => {
+                let focus = CompostorFocus::Layer(layer.clone());
+                if current.as_ref() != Some(&focus) {
+                    keyboard.set_focus(self, Some(focus), SERIAL_COUNTER.next_serial());
+                }
+            }
+            None => {
+                let keeps_focus = match current {
+                    Some(CompostorFocus::Layer(layer)) => {
+                        layers.contains(&layer)
+                            && layer.cached_state().keyboard_interactivity
+                                != KeyboardInteractivity::None
+                    }
+                    _ => true,
+                };
+                if !keeps_focus {
+                    // back to the window that had the keyboard before
+                    let serial = SERIAL_COUNTER.next_serial();
+                    match self.focused_window() {
+                        Some(window) => self.focus_window(&window, serial),
+                        None => keyboard.set_focus(self, None, serial),
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use mlua::{Function, Lua, Table};
+use smithay::desktop::layer_map_for_output;
+use smithay::output::Output;
+use smithay::reexports::wayland_protocols::xdg::shell::server::xdg_toplevel;
+use smithay::reexports::wayland_server::Resource;
+use smithay::utils::{Logical, Point, Rectangle, Size};
+
+use crate::state::{BackendDataExt, Compostate};
+use crate::tags::{visible_tags, MAX_TAGS};
+use crate::windowdata::{initial_configure_sent, CompostoWindow};
+
+pub const DEFAULT_MASTER_COUNT: usize = 1;
+pub const DEFAULT_MASTER_RATIO: f64 = 0.55;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Layout {
+    /// One column of master windows on the left, the remaining windows stacked on the right.
+    MasterStack,
+    /// Every window takes up the whole area.
+    Monocle,
+    /// Windows in rows and columns of (almost) equal size.
+    Grid,
+    /// A layout function registered by the config with `composto.define_layout`.
+    Lua(String),
+}
+
+impl Layout {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "master_stack" | "tile" => Some(Self::MasterStack),
+            "monocle" => Some(Self::Monocle),
+            "grid" => Some(Self::Grid),
+            _ => None,
+        }
+    }
+}
+
+/// How a window is placed on its output, from the bottom of the stack to the top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Placement {
+    Tiled,
+    Maximized,
+    Floating,
+    Fullscreen,
+}
+
+/// Layout settings of a single tag.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagLayout {
+    pub layout: Layout,
+    pub master_count: usize,
+    pub master_ratio: f64,
+}
+
+impl TagLayout {
+    pub fn new(layout: Layout) -> Self {
+        Self {
+            layout,
+            master_count: DEFAULT_MASTER_COUNT,
+            master_ratio: DEFAULT_MASTER_RATIO,
+        }
+    }
+
+    /// Compute the geometry of `count` windows inside `area`, in stacking order.
+    pub fn arrange(
+        &self,
+        area: Rectangle<i32, Logical>,
+        count: usize,
+    ) -> Vec<Rectangle<i32, Logical>> {
+        match self.layout {
+            // lua layouts are run by the compositor, which falls back to this when they fail
+            Layout::MasterStack | Layout::Lua(_) => {
+                master_stack(area, count, self.master_count, self.master_ratio)
+            }
+            Layout::Monocle => vec![area; count],
+            Layout::Grid => grid(area, count),
+        }
+    }
+}
+
+/// Split `area` into `count` rows of equal height, giving the remainder to the last one.
+fn rows(area: Rectangle<i32, Logical>, count: usize) -> Vec<Rectangle<i32, Logical>> {
+    if count == 0 {
+        return Vec::new();
+    }
+    let height = area.size.h / count as i32;
+    (0..count as i32)
+        .map(|i| {
+            let y = area.loc.y + i * height;
+            let h = if i == count as i32 - 1 {
+                area.loc.y + area.size.h - y
+            } else {
+                height
+            };
+            Rectangle::from_loc_and_size((area.loc.x, y), (area.size.w, h))
+        })
+        .collect()
+}
+
+fn master_stack(
+    area: Rectangle<i32, Logical>,
+    count: usize,
+    master_count: usize,
+    master_ratio: f64,
+) -> Vec<Rectangle<i32, Logical>> {
+    let masters = master_count.min(count);
+    let stacked = count - masters;
+
+    // with only one of the two columns in use it gets the whole area
+    if masters == 0 || stacked == 0 {
+        return rows(area, count);
+    }
+
+    let master_width = (area.size.w as f64 * master_ratio).round() as i32;
+    let master_area = Rectangle::from_loc_and_size(area.loc, (master_width, area.size.h));
+    let stack_area = Rectangle::from_loc_and_size(
+        (area.loc.x + master_width, area.loc.y),
+        (area.size.w - master_width, area.size.h),
+    );
+
+    let mut geometries = rows(master_area, masters);
+    geometries.extend(rows(stack_area, stacked));
+    geometries
+}
+
+fn grid(area: Rectangle<i32, Logical>, count: usize) -> Vec<Rectangle<i32, Logical>> {
+    if count == 0 {
+        return Vec::new();
+    }
+    let columns = (count as f64).sqrt().ceil() as usize;
+    let row_count = (count + columns - 1) / columns;
+
+    rows(area, row_count)
+        .into_iter()
+        .enumerate()
+        .flat_map(|(row, row_area)| {
+            // the last row may hold fewer windows, which then get wider
+            let in_row = columns.min(count - row * columns);
+            let width = row_area.size.w / in_row as i32;
+            (0..in_row as i32).map(move |i| {
+                let x = row_area.loc.x + i * width;
+                let w = if i == in_row as i32 - 1 {
+                    row_area.loc.x + row_area.size.w - x
+                } else {
+                    width
+                };
+                Rectangle::from_loc_and_size((x, row_area.loc.y), (w, row_area.size.h))
+            })
+        })
+        .collect()
+}
+
+/// Fit `geometry` inside `area`, shrinking it when it is too big and moving it when it sticks out.
+fn clamp_geometry(
+    geometry: Rectangle<i32, Logical>,
+    area: Rectangle<i32, Logical>,
+) -> Rectangle<i32, Logical> {
+    let w = geometry.size.w.clamp(1, area.size.w.max(1));
+    let h = geometry.size.h.clamp(1, area.size.h.max(1));
+    let x = geometry
+        .loc
+        .x
+        .clamp(area.loc.x, (area.loc.x + area.size.w - w).max(area.loc.x));
+    let y = geometry
+        .loc
+        .y
+        .clamp(area.loc.y, (area.loc.y + area.size.h - h).max(area.loc.y));
+    Rectangle::from_loc_and_size((x, y), (w, h))
+}
+
+fn rect_to_table<'lua>(lua: &'lua Lua, rect: Rectangle<i32, Logical>) -> mlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("x", rect.loc.x)?;
+    table.set("y", rect.loc.y)?;
+    table.set("w", rect.size.w)?;
+    table.set("h", rect.size.h)?;
+    Ok(table)
+}
+
+fn table_to_rect(table: Table<'_>) -> mlua::Result<Rectangle<i32, Logical>> {
+    let get = |key: &str| -> mlua::Result<i32> { Ok(table.get::<_, f64>(key)?.round() as i32) };
+    Ok(Rectangle::from_loc_and_size(
+        (get("x")?, get("y")?),
+        (get("w")?, get("h")?),
+    ))
+}
+
+/// The tag whose layout is used for an output: the first of the tags visible on it.
+pub fn layout_tag(output: &Output) -> usize {
+    (visible_tags(output).trailing_zeros() as usize).min(MAX_TAGS - 1)
+}
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    pub fn tag_layout(&self, output: &Output) -> &TagLayout {
+        &self.tag_layouts[layout_tag(output)]
+    }
+
+    fn tag_layout_mut(&mut self, tags: Option<u32>) -> Option<&mut TagLayout> {
+        let tag = match tags {
+            Some(tags) if tags != 0 => (tags.trailing_zeros() as usize).min(MAX_TAGS - 1),
+            Some(_) => return None,
+            // before any output exists, the tag every output starts out with
+            None => self
+                .active_output()
+                .map(|output| layout_tag(&output))
+                .unwrap_or(0),
+        };
+        self.tag_layouts.get_mut(tag)
+    }
+
+    /// Use `layout` for a tag, or for the tag currently shown on the active output.
+    pub fn set_layout(&mut self, layout: Layout, tags: Option<u32>) {
+        if let Some(tag_layout) = self.tag_layout_mut(tags) {
+            tag_layout.layout = layout;
+            self.arrange_all();
+        }
+    }
+
+    pub fn inc_master_count(&mut self, delta: i32) {
+        if let Some(tag_layout) = self.tag_layout_mut(None) {
+            tag_layout.master_count = (tag_layout.master_count as i32 + delta).max(0) as usize;
+            self.arrange_all();
+        }
+    }
+
+    pub fn inc_master_ratio(&mut self, delta: f64) {
+        if let Some(tag_layout) = self.tag_layout_mut(None) {
+            tag_layout.master_ratio = (tag_layout.master_ratio + delta).clamp(0.05, 0.95);
+            self.arrange_all();
+        }
+    }
+
+    /// Run a layout function defined in lua. It gets the usable area, a descriptor of every tiled
+    /// window and the layout parameters of the tag, and returns one rectangle per window.
+    fn lua_arrange(
+        &self,
+        name: &str,
+        area: Rectangle<i32, Logical>,
+        windows: &[CompostoWindow],
+        tag_layout: &TagLayout,
+    ) -> mlua::Result<Vec<Rectangle<i32, Logical>>> {
+        let lua = &self.config.lua;
+        let function = self
+            .config
+            .with_data(|data| {
+                data.layouts
+                    .get(name)
+                    .map(|key| lua.registry_value::<Function>(key))
+            })
+            .ok_or_else(|| {
+                mlua::Error::RuntimeError(format!("layout `{name}` is not defined"))
+            })??;
+
+        let descriptors = lua.create_table()?;
+        for (i, window) in windows.iter().enumerate() {
+            let descriptor = lua.create_table()?;
+            let tags = window.state().tags;
+            descriptor.set("app_id", window.app_id())?;
+            descriptor.set("title", window.title())?;
+            descriptor.set("tags", tags)?;
+            descriptor.set("tag", tags.trailing_zeros() + 1)?;
+            descriptors.set(i + 1, descriptor)?;
+        }
+
+        let params = lua.create_table()?;
+        params.set("master_count", tag_layout.master_count)?;
+        params.set("master_ratio", tag_layout.master_ratio)?;
+
+        let geometries: Vec<Table> =
+            function.call((rect_to_table(lua, area)?, descriptors, params))?;
+        if geometries.len() != windows.len() {
+            return Err(mlua::Error::RuntimeError(format!(
+                "returned {} rectangles for {} windows",
+                geometries.len(),
+                windows.len()
+            )));
+        }
+
+        geometries
+            .into_iter()
+            .map(|table| Ok(clamp_geometry(table_to_rect(table)?, area)))
+            .collect()
+    }
+
+    /// The part of `output` windows go in: all of it but the exclusive zones of panels and docks.
+    pub fn usable_area(&self, output: &Output) -> Option<Rectangle<i32, Logical>> {
+        let output_geometry = self.space.output_geometry(output)?;
+        let mut zone = layer_map_for_output(output).non_exclusive_zone();
+        zone.loc += output_geometry.loc;
+        Some(zone)
+    }
+
+    pub fn arrange_all(&mut self) {
+        let outputs = self.space.outputs().cloned().collect::<Vec<_>>();
+        for output in outputs {
+            self.arrange_output(&output);
+        }
+    }
+
+    /// Tile the windows visible on `output` and send them their new size. Maximized, floating and
+    /// fullscreen windows are left out of the layout and stacked above it, in that order.
+    pub fn arrange_output(&mut self, output: &Output) {
+        layer_map_for_output(output).arrange();
+        let (Some(area), Some(output_geometry)) =
+            (self.usable_area(output), self.space.output_geometry(output))
+        else {
+            return;
+        };
+
+        let visible = self
+            .windows
+            .iter()
+            .filter(|window| {
+                self.is_window_visible(window)
+                    && self.window_output(window).as_ref() == Some(output)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let placed = |placement: Placement| {
+            visible
+                .iter()
+                .filter(|window| window.state().placement() == placement)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let windows = placed(Placement::Tiled);
+        let mut maximized = placed(Placement::Maximized);
+        let mut floating = placed(Placement::Floating);
+        let mut fullscreen = placed(Placement::Fullscreen);
+
+        let tag_layout = self.tag_layout(output).clone();
+        let geometries = match &tag_layout.layout {
+            Layout::Lua(name) => self
+                .lua_arrange(name, area, &windows, &tag_layout)
+                .unwrap_or_else(|e| {
+                    eprintln!("layout: `{name}` failed, falling back to master_stack: {e}");
+                    tag_layout.arrange(area, windows.len())
+                }),
+            _ => tag_layout.arrange(area, windows.len()),
+        };
+
+        // mapping raises, so every group is mapped bottom to top to keep its stacking order, with
+        // windows that were not mapped yet going on top
+        let stacking = self.space.elements().cloned().collect::<Vec<_>>();
+        let stack_position = |window: &CompostoWindow| {
+            stacking
+                .iter()
+                .position(|other| other == window)
+                .unwrap_or(usize::MAX)
+        };
+
+        let mut tiled = windows.into_iter().zip(geometries).collect::<Vec<_>>();
+        tiled.sort_by_key(|(window, _)| stack_position(window));
+        for (window, geometry) in tiled {
+            let location = self.fit_frame(&window, geometry, area, Placement::Tiled);
+            window.state().location = location;
+            self.space.map_element(window, location, false);
+        }
+
+        // maximized and fullscreen windows keep the location they had, to go back to it
+        maximized.sort_by_key(|window| stack_position(window));
+        for window in maximized {
+            let location = self.fit_frame(&window, area, area, Placement::Maximized);
+            self.space.map_element(window, location, false);
+        }
+
+        // floating windows keep their place, above the tiled ones
+        floating.sort_by_key(|window| stack_position(window));
+        for window in floating {
+            let size = window.state().floating_size;
+            configure_window(&window, size, area.size, Placement::Floating);
+            let location = window.state().location;
+            self.space.map_element(window, location, false);
+        }
+
+        // the whole output, without decorations
+        fullscreen.sort_by_key(|window| stack_position(window));
+        for window in fullscreen {
+            if let Some(toplevel) = window.0.toplevel() {
+                let wl_output = toplevel
+                    .wl_surface()
+                    .client()
+                    .and_then(|client| output.client_outputs(&client).into_iter().next());
+                toplevel.with_pending_state(|state| state.fullscreen_output = wl_output);
+            }
+            let size = Some(output_geometry.size);
+            configure_window(&window, size, output_geometry.size, Placement::Fullscreen);
+            self.space.map_element(window, output_geometry.loc, false);
+        }
+        self.update_surface_scales(output);
+        self.schedule_render();
+    }
+
+    /// Size `window` so its frame fills `frame`, returning where the window goes.
+    fn fit_frame(
+        &self,
+        window: &CompostoWindow,
+        frame: Rectangle<i32, Logical>,
+        area: Rectangle<i32, Logical>,
+        placement: Placement,
+    ) -> Point<i32, Logical> {
+        // server-side decorations take their share of the frame
+        let (offset, extra) = self.decoration_insets(window);
+        let size = Size::from((
+            (frame.size.w - extra.w).max(1),
+            (frame.size.h - extra.h).max(1),
+        ));
+        configure_window(window, Some(size), area.size, placement);
+        frame.loc + offset
+    }
+}
+
+/// Send `window` its size, the bounds it should stay within and the xdg states going with
+/// `placement`.
+fn configure_window(
+    window: &CompostoWindow,
+    size: Option<Size<i32, Logical>>,
+    bounds: Size<i32, Logical>,
+    placement: Placement,
+) {
+    let Some(toplevel) = window.0.toplevel() else {
+        return;
+    };
+    let tiled = placement == Placement::Tiled;
+    toplevel.with_pending_state(|state| {
+        state.size = size;
+        state.bounds = Some(bounds);
+        for tiled_state in [
+            xdg_toplevel::State::TiledLeft,
+            xdg_toplevel::State::TiledRight,
+            xdg_toplevel::State::TiledTop,
+            xdg_toplevel::State::TiledBottom,
+        ] {
+            if tiled {
+                state.states.set(tiled_state);
+            } else {
+                state.states.unset(tiled_state);
+            }
+        }
+        if placement == Placement::Maximized {
+            state.states.set(xdg_toplevel::State::Maximized);
+        } else {
+            state.states.unset(xdg_toplevel::State::Maximized);
+        }
+        if placement == Placement::Fullscreen {
+            state.states.set(xdg_toplevel::State::Fullscreen);
+        } else {
+            state.states.unset(xdg_toplevel::State::Fullscreen);
+            state.fullscreen_output = None;
+        }
+    });
+    // before the initial commit the configure is sent by the commit handler
+    if initial_configure_sent(toplevel) {
+        toplevel.send_pending_configure();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, w: i32, h: i32) -> Rectangle<i32, Logical> {
+        Rectangle::from_loc_and_size((x, y), (w, h))
+    }
+
+    #[test]
+    fn master_stack_splits_masters_and_stack() {
+        let layout = TagLayout::new(Layout::MasterStack);
+        let area = rect(0, 0, 1000, 600);
+        assert!(layout.arrange(area, 0).is_empty());
+        assert_eq!(layout.arrange(area, 1), vec![area]);
+        assert_eq!(
+            layout.arrange(area, 3),
+            vec![
+                rect(0, 0, 550, 600),
+                rect(550, 0, 450, 300),
+                rect(550, 300, 450, 300),
+            ]
+        );
+    }
+
+    #[test]
+    fn master_stack_without_masters_uses_one_column() {
+        let layout = TagLayout {
+            master_count: 0,
+            ..TagLayout::new(Layout::MasterStack)
+        };
+        assert_eq!(
+            layout.arrange(rect(0, 0, 1000, 600), 2),
+            vec![rect(0, 0, 1000, 300), rect(0, 300, 1000, 300)]
+        );
+    }
+
+    #[test]
+    fn rows_give_the_remainder_to_the_last_row() {
+        assert_eq!(
+            rows(rect(10, 20, 100, 100), 3),
+            vec![
+                rect(10, 20, 100, 33),
+                rect(10, 53, 100, 33),
+                rect(10, 86, 100, 34)
+            ]
+        );
+    }
+
+    #[test]
+    fn grid_widens_the_last_row() {
+        let layout = TagLayout::new(Layout::Grid);
+        assert_eq!(
+            layout.arrange(rect(0, 0, 900, 600), 5),
+            vec![
+                rect(0, 0, 300, 300),
+                rect(300, 0, 300, 300),
+                rect(600, 0, 300, 300),
+                rect(0, 300, 450, 300),
+                rect(450, 300, 450, 300),
+            ]
+        );
+    }
+
+    #[test]
+    fn monocle_gives_every_window_the_area() {
+        let layout = TagLayout::new(Layout::Monocle);
+        let area = rect(0, 30, 800, 570);
+        assert_eq!(layout.arrange(area, 2), vec![area, area]);
+    }
+
+    #[test]
+    fn clamp_geometry_keeps_windows_inside() {
+        let area = rect(0, 0, 1000, 600);
+        assert_eq!(
+            clamp_geometry(rect(900, 500, 200, 200), area),
+            rect(800, 400, 200, 200)
+        );
+        assert_eq!(
+            clamp_geometry(rect(-10, -10, 2000, 50), area),
+            rect(0, 0, 1000, 50)
+        );
+    }
+}
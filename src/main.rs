@@ -1,20 +1,72 @@
+mod action;
+mod activation;
 mod backends;
+mod config;
+mod cursor;
+mod decorations;
+mod floating;
 mod focus;
+mod fullscreen;
+mod hooks;
+mod input;
+mod ipc;
+mod keybindings;
+mod keyboard;
+mod layers;
+mod layout;
+mod popups;
+mod render;
+mod rules;
+mod scale;
+mod selection;
 mod state;
+mod tags;
 mod windowdata;
 
+use crate::backends::headless::{self, run_headless};
 use crate::backends::{udev::run_udev, x11::run_x11};
+use crate::config::Config;
+use crate::ipc::run_msg;
 use anyhow::Result;
+use std::path::PathBuf;
 
-const USAGE: &'_ str = "usage: composto {{x11 | udev}}";
+const USAGE: &'_ str =
+    "usage: composto [--config PATH] {x11 | udev | headless [WIDTHxHEIGHT] | msg COMMAND...}";
 
 fn main() -> Result<()> {
-    let args: Vec<String> = ::std::env::args().collect();
+    let mut args: Vec<String> = ::std::env::args().collect();
+
+    let config_path = match args.iter().position(|arg| arg == "--config") {
+        Some(i) if i + 1 < args.len() => {
+            let path = PathBuf::from(args.remove(i + 1));
+            args.remove(i);
+            Some(path)
+        }
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            return Ok(());
+        }
+        None => None,
+    };
 
     if let Some(arg) = args.get(1) {
         match arg.as_ref() {
-            "x11" => run_x11()?,
-            "udev" => run_udev()?,
+            "x11" => run_x11(Config::load(config_path))?,
+            "udev" => run_udev(Config::load(config_path))?,
+            "msg" => run_msg(&args[2..])?,
+            "headless" => {
+                let size = match args.get(2) {
+                    Some(size) => match headless::parse_size(size) {
+                        Some(size) => size,
+                        None => {
+                            eprintln!("{}", USAGE);
+                            return Ok(());
+                        }
+                    },
+                    None => headless::DEFAULT_SIZE.into(),
+                };
+                run_headless(Config::load(config_path), size)?
+            }
             _ => eprintln!("{}", USAGE),
         }
     } else {
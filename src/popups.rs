@@ -0,0 +1,130 @@
+use smithay::desktop::{
+    find_popup_root_surface, get_popup_toplevel_coords, layer_map_for_output, PopupKeyboardGrab,
+    PopupKind, PopupPointerGrab, PopupUngrabStrategy, WindowSurfaceType,
+};
+use smithay::input::{pointer::Focus, Seat};
+use smithay::reexports::wayland_server::protocol::{wl_seat::WlSeat, wl_surface::WlSurface};
+use smithay::utils::{Logical, Point, Rectangle, Serial};
+use smithay::wayland::compositor::with_states;
+use smithay::wayland::shell::xdg::PopupSurface;
+
+use crate::focus::CompostorFocus;
+use crate::scale::send_scale;
+use crate::state::{BackendDataExt, Compostate};
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    /// Send the initial configure of an xdg popup, returning whether `surface` is one.
+    pub fn popup_commit(&mut self, surface: &WlSurface) -> bool {
+        self.popups.commit(surface);
+        match self.popups.find_popup(surface) {
+            Some(PopupKind::Xdg(popup)) => {
+                if !popup.is_initial_configure_sent() {
+                    if let Some(output) = self.surface_output(surface) {
+                        with_states(surface, |states| send_scale(surface, states, &output));
+                    }
+                    // a popup whose parent went away in the meantime has nothing to configure
+                    let _ = popup.send_configure();
+                }
+                true
+            }
+            Some(_) => true,
+            None => false,
+        }
+    }
+
+    /// The window or layer surface whose main surface is `root`. Popups rooted there return the
+    /// focus to it once their grab ends.
+    pub fn root_focus(&self, root: &WlSurface) -> Option<CompostorFocus> {
+        if let Some(window) = self.window_for_surface(root) {
+            return Some(CompostorFocus::Window(window));
+        }
+        self.layer_surfaces()
+            .into_iter()
+            .find(|layer| layer.wl_surface() == root)
+            .map(CompostorFocus::Layer)
+    }
+
+    /// Where the surface at the root of a popup tree is, and the output it is shown on.
+    fn popup_root_geometry(
+        &self,
+        root: &WlSurface,
+    ) -> Option<(Point<i32, Logical>, Rectangle<i32, Logical>)> {
+        if let Some(window) = self.window_for_surface(root) {
+            let location = self.space.element_geometry(&window)?.loc;
+            let output = self.window_output(&window)?;
+            return Some((location, self.space.output_geometry(&output)?));
+        }
+
+        let output = self.layer_output(root)?;
+        let output_geometry = self.space.output_geometry(&output)?;
+        let map = layer_map_for_output(&output);
+        let layer = map.layer_for_surface(root, WindowSurfaceType::TOPLEVEL)?;
+        let location = map.layer_geometry(layer)?.loc + output_geometry.loc;
+        Some((location, output_geometry))
+    }
+
+    /// Apply the constraint adjustments of the popup's positioner (flip, slide, resize) so it
+    /// stays on the output of its parent.
+    pub fn unconstrain_popup(&self, popup: &PopupSurface) {
+        let kind = PopupKind::Xdg(popup.clone());
+        let Ok(root) = find_popup_root_surface(&kind) else {
+            return;
+        };
+        let Some((root_location, mut target)) = self.popup_root_geometry(&root) else {
+            return;
+        };
+
+        // the positioner works relative to the parent of the popup
+        target.loc -= root_location;
+        target.loc -= get_popup_toplevel_coords(&kind);
+        popup.with_pending_state(|state| {
+            state.geometry = state.positioner.get_unconstrained_geometry(target);
+        });
+    }
+
+    /// Start an explicit grab for a popup, which is dismissed when the user clicks outside of
+    /// the popups of the same client.
+    pub fn grab_popup(&mut self, surface: PopupSurface, seat: WlSeat, serial: Serial) {
+        let Some(seat) = Seat::<Self>::from_resource(&seat) else {
+            return;
+        };
+        let kind = PopupKind::Xdg(surface);
+        let Some(root) = find_popup_root_surface(&kind)
+            .ok()
+            .and_then(|root| self.root_focus(&root))
+        else {
+            return;
+        };
+
+        let mut grab = match self.popups.grab_popup(root, kind, &seat, serial) {
+            Ok(grab) => grab,
+            Err(e) => {
+                eprintln!("popup: unable to grab: {e:?}");
+                return;
+            }
+        };
+
+        // only the input that caused the popup (or an enclosing grab) may start a grab
+        if let Some(keyboard) = seat.get_keyboard() {
+            if keyboard.is_grabbed()
+                && !(keyboard.has_grab(serial)
+                    || keyboard.has_grab(grab.previous_serial().unwrap_or(serial)))
+            {
+                grab.ungrab(PopupUngrabStrategy::All);
+                return;
+            }
+            keyboard.set_focus(self, grab.current_grab(), serial);
+            keyboard.set_grab(PopupKeyboardGrab::new(&grab), serial);
+        }
+        if let Some(pointer) = seat.get_pointer() {
+            if pointer.is_grabbed()
+                && !(pointer.has_grab(serial)
+                    || pointer.has_grab(grab.previous_serial().unwrap_or_else(|| grab.serial())))
+            {
+                grab.ungrab(PopupUngrabStrategy::All);
+                return;
+            }
+            pointer.set_grab(self, PopupPointerGrab::new(&grab), serial, Focus::Keep);
+        }
+    }
+}
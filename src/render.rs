@@ -0,0 +1,225 @@
+use std::time::Duration;
+
+use smithay::backend::renderer::damage::{
+    OutputDamageTracker, OutputDamageTrackerError, RenderOutputResult,
+};
+use smithay::backend::renderer::element::memory::MemoryRenderBufferRenderElement;
+use smithay::backend::renderer::element::surface::{
+    render_elements_from_surface_tree, WaylandSurfaceRenderElement,
+};
+use smithay::backend::renderer::element::{AsRenderElements, Kind, RenderElementStates};
+use smithay::backend::renderer::{ImportAll, ImportMem, Renderer, Texture};
+use smithay::desktop::space::{space_render_elements, SpaceRenderElements};
+use smithay::desktop::utils::{
+    send_frames_surface_tree, surface_presentation_feedback_flags_from_states,
+    take_presentation_feedback_surface_tree, OutputPresentationFeedback,
+};
+use smithay::desktop::{layer_map_for_output, Space};
+use smithay::input::pointer::CursorImageStatus;
+use smithay::output::Output;
+use smithay::reexports::calloop::timer::{TimeoutAction, Timer};
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::render_elements;
+use smithay::utils::{Logical, Point, Scale};
+use smithay::wayland::compositor::SurfaceData;
+use smithay::wayland::shell::wlr_layer::Layer;
+
+use crate::state::{BackendDataExt, Compostate};
+use crate::windowdata::{CompostoWindow, WindowRenderElement};
+
+pub const CLEAR_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+render_elements! {
+    pub OutputRenderElement<R> where R: ImportAll + ImportMem;
+    Space=SpaceRenderElements<R, WindowRenderElement<R>>,
+    Window=WindowRenderElement<R>,
+    Surface=WaylandSurfaceRenderElement<R>,
+    Cursor=MemoryRenderBufferRenderElement<R>,
+}
+
+/// The fullscreen window covering `output`, the topmost one if there are several.
+pub fn fullscreen_window(space: &Space<CompostoWindow>, output: &Output) -> Option<CompostoWindow> {
+    space
+        .elements_for_output(output)
+        .filter(|window| window.state().fullscreen)
+        .last()
+        .cloned()
+}
+
+/// Collect everything that should be drawn on `output`, front to back. `drag_icon` is the icon
+/// of a drag and drop operation and its location, drawn above everything else.
+pub fn output_elements<R>(
+    output: &Output,
+    space: &Space<CompostoWindow>,
+    drag_icon: Option<&(WlSurface, Point<i32, Logical>)>,
+    renderer: &mut R,
+) -> Vec<OutputRenderElement<R>>
+where
+    R: Renderer + ImportAll + ImportMem,
+    <R as Renderer>::TextureId: Texture + Clone + 'static,
+{
+    let mut elements = Vec::new();
+
+    if let (Some((icon, location)), Some(output_geometry)) =
+        (drag_icon, space.output_geometry(output))
+    {
+        let scale = output.current_scale().fractional_scale();
+        let location = (*location - output_geometry.loc).to_physical_precise_round(scale);
+        elements.extend(
+            render_elements_from_surface_tree(
+                renderer,
+                icon,
+                location,
+                scale,
+                1.0,
+                Kind::Unspecified,
+            )
+            .into_iter()
+            .map(OutputRenderElement::Surface),
+        );
+    }
+
+    // a fullscreen window only leaves overlays like notifications and lock screens above it, and
+    // covers everything else
+    if let (Some(window), Some(output_geometry)) = (
+        fullscreen_window(space, output),
+        space.output_geometry(output),
+    ) {
+        let scale = output.current_scale().fractional_scale();
+        let map = layer_map_for_output(output);
+        for layer in map.layers_on(Layer::Overlay).rev() {
+            let Some(geometry) = map.layer_geometry(layer) else {
+                continue;
+            };
+            elements.extend(
+                AsRenderElements::<R>::render_elements::<WaylandSurfaceRenderElement<R>>(
+                    layer,
+                    renderer,
+                    geometry.loc.to_physical_precise_round(scale),
+                    Scale::from(scale),
+                    1.0,
+                )
+                .into_iter()
+                .map(OutputRenderElement::Surface),
+            );
+        }
+        if let Some(location) = space.element_location(&window) {
+            let location = location - window.0.geometry().loc - output_geometry.loc;
+            elements.extend(
+                AsRenderElements::<R>::render_elements::<WindowRenderElement<R>>(
+                    &window,
+                    renderer,
+                    location.to_physical_precise_round(scale),
+                    Scale::from(scale),
+                    1.0,
+                )
+                .into_iter()
+                .map(OutputRenderElement::Window),
+            );
+        }
+        return elements;
+    }
+
+    // this can only fail when the output has no mode set, in which case there is nothing to draw
+    elements.extend(
+        space_render_elements(renderer, [space], output, 1.0)
+            .unwrap_or_default()
+            .into_iter()
+            .map(OutputRenderElement::Space),
+    );
+    elements
+}
+
+/// Render a single output of the space. Every backend that renders through an
+/// [`OutputDamageTracker`] goes through this function, so whatever ends up on screen is built the
+/// same way regardless of the renderer behind it.
+pub fn render_output<R>(
+    output: &Output,
+    space: &Space<CompostoWindow>,
+    drag_icon: Option<&(WlSurface, Point<i32, Logical>)>,
+    renderer: &mut R,
+    age: usize,
+    damage_tracker: &mut OutputDamageTracker,
+) -> Result<RenderOutputResult, OutputDamageTrackerError<R>>
+where
+    R: Renderer + ImportAll + ImportMem,
+    <R as Renderer>::TextureId: Texture + Clone + 'static,
+{
+    let elements = output_elements(output, space, drag_icon, renderer);
+    damage_tracker.render_output(renderer, age, &elements, CLEAR_COLOR)
+}
+
+/// Time between two frames of `output`, assuming 60Hz when it has no mode.
+pub fn refresh_interval(output: &Output) -> Duration {
+    output
+        .current_mode()
+        .map(|mode| Duration::from_micros(1_000_000_000 / mode.refresh.max(1) as u64))
+        .unwrap_or(Duration::from_micros(16_667))
+}
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    /// Tell every surface shown on `output` that now is a good time to draw its next frame.
+    pub fn send_frames(&self, output: &Output) {
+        let time = self.clock.now();
+        let throttle = Some(Duration::ZERO);
+        let on_output = |_: &WlSurface, _: &SurfaceData| Some(output.clone());
+
+        for window in self.space.elements_for_output(output) {
+            window.0.send_frame(output, time, throttle, on_output);
+        }
+        for layer in layer_map_for_output(output).layers() {
+            layer.send_frame(output, time, throttle, on_output);
+        }
+        if let Some(icon) = &self.dnd_icon {
+            send_frames_surface_tree(icon, output, time, throttle, on_output);
+        }
+        if let CursorImageStatus::Surface(cursor) = &self.cursor_status {
+            send_frames_surface_tree(cursor, output, time, throttle, on_output);
+        }
+    }
+
+    /// Collect the presentation feedback of every surface drawn in the frame just rendered on
+    /// `output`, to be fired once the backend knows when it reached the screen.
+    pub fn take_presentation_feedback(
+        &self,
+        output: &Output,
+        states: &RenderElementStates,
+    ) -> OutputPresentationFeedback {
+        let mut feedback = OutputPresentationFeedback::new(output);
+        let on_output = |_: &WlSurface, _: &SurfaceData| Some(output.clone());
+        let flags = |surface: &WlSurface, _: &SurfaceData| {
+            surface_presentation_feedback_flags_from_states(surface, states)
+        };
+
+        for window in self.space.elements_for_output(output) {
+            window
+                .0
+                .take_presentation_feedback(&mut feedback, on_output, flags);
+        }
+        for layer in layer_map_for_output(output).layers() {
+            layer.take_presentation_feedback(&mut feedback, on_output, flags);
+        }
+        if let Some(icon) = &self.dnd_icon {
+            take_presentation_feedback_surface_tree(icon, &mut feedback, on_output, flags);
+        }
+        if let CursorImageStatus::Surface(cursor) = &self.cursor_status {
+            take_presentation_feedback_surface_tree(cursor, &mut feedback, on_output, flags);
+        }
+        feedback
+    }
+
+    /// Send the frame callbacks of `output` one refresh from now. This is for frames that had no
+    /// damage and so never reach the screen: there is no vblank to wait for, but clients drawing
+    /// continuously must still be held to the refresh rate.
+    pub fn send_frames_after_refresh(&mut self, output: &Output) {
+        let output = output.clone();
+        let timer = Timer::from_duration(refresh_interval(&output));
+        let res = self.loop_handle.insert_source(timer, move |_, _, data| {
+            data.state.send_frames(&output);
+            TimeoutAction::Drop
+        });
+        if let Err(e) = res {
+            eprintln!("render: unable to schedule frame callbacks: {e}");
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use mlua::{Lua, Table, Value};
+use regex::Regex;
+use smithay::utils::{Logical, Point, Size};
+use smithay::wayland::compositor::with_states;
+use smithay::wayland::shell::xdg::SurfaceCachedState;
+
+use crate::config::tag_mask;
+use crate::decorations::{parse_decoration_mode, DecorationMode};
+use crate::state::{BackendDataExt, Compostate};
+use crate::tags::visible_tags;
+use crate::windowdata::CompostoWindow;
+
+/// Which windows a rule applies to. Criteria left out match every window.
+#[derive(Debug, Clone, Default)]
+pub struct RuleMatch {
+    pub app_id: Option<Regex>,
+    pub title: Option<Regex>,
+    /// Whether the window has a parent toplevel, as dialogs usually do.
+    pub parent: Option<bool>,
+    /// Bounds on the minimum size the client asks for, see [`min_size_hint`].
+    pub min_size_at_least: Option<Size<i32, Logical>>,
+    pub min_size_at_most: Option<Size<i32, Logical>>,
+}
+
+impl RuleMatch {
+    fn from_table(table: Table<'_>) -> mlua::Result<Self> {
+        let regex = |key: &str| -> mlua::Result<Option<Regex>> {
+            table
+                .get::<_, Option<String>>(key)?
+                .map(|pattern| {
+                    Regex::new(&pattern).map_err(|e| {
+                        mlua::Error::RuntimeError(format!("invalid {key} pattern: {e}"))
+                    })
+                })
+                .transpose()
+        };
+        Ok(Self {
+            app_id: regex("app_id")?,
+            title: regex("title")?,
+            parent: table.get("parent")?,
+            min_size_at_least: table
+                .get::<_, Option<Table>>("min_size_at_least")?
+                .map(size)
+                .transpose()?,
+            min_size_at_most: table
+                .get::<_, Option<Table>>("min_size_at_most")?
+                .map(size)
+                .transpose()?,
+        })
+    }
+
+    pub fn matches(&self, window: &CompostoWindow) -> bool {
+        let pattern_matches = |pattern: &Option<Regex>, value: Option<String>| match pattern {
+            Some(pattern) => value.map_or(false, |value| pattern.is_match(&value)),
+            None => true,
+        };
+        if !pattern_matches(&self.app_id, window.app_id())
+            || !pattern_matches(&self.title, window.title())
+        {
+            return false;
+        }
+
+        if let Some(parent) = self.parent {
+            let has_parent = window
+                .0
+                .toplevel()
+                .map_or(false, |toplevel| toplevel.parent().is_some());
+            if has_parent != parent {
+                return false;
+            }
+        }
+
+        // windows without a minimum size match neither bound
+        let size = window.state().min_size_hint;
+        let known = size.w > 0 && size.h > 0;
+        let at_least = self
+            .min_size_at_least
+            .map_or(true, |min| known && size.w >= min.w && size.h >= min.h);
+        let at_most = self
+            .min_size_at_most
+            .map_or(true, |max| known && size.w <= max.w && size.h <= max.h);
+        at_least && at_most
+    }
+}
+
+/// A window rule registered with `composto.rule`: the windows it matches and the properties
+/// they get. Properties left out are not touched.
+#[derive(Debug, Clone, Default)]
+pub struct WindowRule {
+    pub matches: RuleMatch,
+    pub tags: Option<u32>,
+    pub output: Option<String>,
+    pub floating: Option<bool>,
+    /// Size and position of floating windows, relative to their output.
+    pub size: Option<Size<i32, Logical>>,
+    pub position: Option<Point<i32, Logical>>,
+    pub opacity: Option<f32>,
+    pub decoration_mode: Option<DecorationMode>,
+    /// Never give the window the keyboard.
+    pub never_focus: Option<bool>,
+}
+
+impl WindowRule {
+    /// Read a rule given to `composto.rule`. Tags given by name must already be defined.
+    pub fn from_table(lua: &Lua, table: Table<'_>) -> mlua::Result<Self> {
+        let matches = match table.get::<_, Option<Table>>("match")? {
+            Some(table) => RuleMatch::from_table(table)?,
+            None => RuleMatch::default(),
+        };
+
+        let tags = match table.get::<_, Value>("tags")? {
+            Value::Nil => None,
+            Value::Table(tags) => {
+                let mut mask = 0;
+                for tag in tags.sequence_values::<Value>() {
+                    mask |= tag_mask(lua, tag?)?;
+                }
+                Some(mask).filter(|mask| *mask != 0)
+            }
+            tag => Some(tag_mask(lua, tag)?),
+        };
+
+        let position = table
+            .get::<_, Option<Table>>("position")?
+            .map(|table| -> mlua::Result<Point<i32, Logical>> {
+                Ok((table.get("x")?, table.get("y")?).into())
+            })
+            .transpose()?;
+
+        Ok(Self {
+            matches,
+            tags,
+            output: table.get("output")?,
+            floating: table.get("floating")?,
+            size: table
+                .get::<_, Option<Table>>("size")?
+                .map(size)
+                .transpose()?,
+            position,
+            opacity: table
+                .get::<_, Option<f32>>("opacity")?
+                .map(|opacity| opacity.clamp(0.0, 1.0)),
+            decoration_mode: table
+                .get::<_, Option<String>>("decorations")?
+                .map(|mode| parse_decoration_mode(&mode))
+                .transpose()?,
+            never_focus: table.get::<_, Option<bool>>("focus")?.map(|focus| !focus),
+        })
+    }
+}
+
+fn size(table: Table<'_>) -> mlua::Result<Size<i32, Logical>> {
+    Ok((
+        table.get::<_, i32>("w")?.max(1),
+        table.get::<_, i32>("h")?.max(1),
+    )
+        .into())
+}
+
+/// The minimum size the client set for `window`, the only hint about its size there is before
+/// the window is first configured. Zero when the client gives no minimum.
+pub fn min_size_hint(window: &CompostoWindow) -> Size<i32, Logical> {
+    window.0.toplevel().map_or_else(Size::default, |toplevel| {
+        with_states(toplevel.wl_surface(), |states| {
+            states.cached_state.current::<SurfaceCachedState>().min_size
+        })
+    })
+}
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    /// Apply the rules matching `window` which were not applied to it yet, in the order they were
+    /// registered. Rules are first evaluated on the initial commit and again whenever the window
+    /// changes its title or app_id, so clients setting those late still get their rules.
+    pub fn apply_window_rules(&mut self, window: &CompostoWindow) {
+        let rules = self.config.with_data(|data| {
+            data.rules
+                .iter()
+                .enumerate()
+                .filter(|(i, rule)| {
+                    let applied = window.state().applied_rules.contains(i);
+                    !applied && rule.matches.matches(window)
+                })
+                .map(|(i, rule)| (i, rule.clone()))
+                .collect::<Vec<_>>()
+        });
+        if rules.is_empty() {
+            return;
+        }
+
+        for (i, rule) in rules {
+            window.state().applied_rules.push(i);
+            self.apply_window_rule(window, rule);
+        }
+        self.update_decorations();
+        self.update_visibility();
+    }
+
+    fn apply_window_rule(&mut self, window: &CompostoWindow, rule: WindowRule) {
+        if let Some(name) = &rule.output {
+            window.state().output = Some(name.clone());
+            // show the window on that output rather than on the tags it was given elsewhere
+            if let (Some(output), None) = (self.output_by_name(name), rule.tags) {
+                window.state().tags = visible_tags(&output);
+            }
+        }
+
+        {
+            let mut state = window.state();
+            if let Some(tags) = rule.tags {
+                state.tags = tags;
+            }
+            if let Some(floating) = rule.floating {
+                state.floating = floating;
+            }
+            if let Some(size) = rule.size {
+                state.floating_size = Some(size);
+            }
+            if let Some(opacity) = rule.opacity {
+                state.opacity = Some(opacity);
+            }
+            if let Some(never_focus) = rule.never_focus {
+                state.never_focus = never_focus;
+            }
+        }
+
+        if rule.position.is_some() || rule.size.is_some() {
+            self.place_floating_window(window, rule.position);
+        }
+
+        if let Some(mode) = rule.decoration_mode {
+            window.state().decoration_mode = Some(mode);
+            // only clients speaking xdg-decoration know the compositor may draw their frame
+            if let Some(toplevel) = window.0.toplevel() {
+                if let Some(requested) = toplevel.with_pending_state(|state| state.decoration_mode)
+                {
+                    self.set_decoration_mode(toplevel, requested);
+                }
+            }
+        }
+    }
+
+    /// Put a floating window at `position` on its output, or center it when no position is given.
+    fn place_floating_window(
+        &mut self,
+        window: &CompostoWindow,
+        position: Option<Point<i32, Logical>>,
+    ) {
+        let Some(area) = self
+            .window_output(window)
+            .and_then(|output| self.space.output_geometry(&output))
+        else {
+            return;
+        };
+        let (offset, extra) = self.decoration_insets(window);
+        let frame = match position {
+            Some(position) => area.loc + position,
+            None => {
+                let size = window.state().floating_size.unwrap_or_default() + extra;
+                area.loc + Point::from(((area.size.w - size.w) / 2, (area.size.h - size.h) / 2))
+            }
+        };
+        window.state().location = frame + offset;
+    }
+
+    /// Apply the rules of a reloaded config to the windows already there, once. The rules they
+    /// match from then on are the ones that did not match now, like for any other window.
+    pub fn reapply_window_rules(&mut self) {
+        for window in self.windows.clone() {
+            window.state().applied_rules.clear();
+            self.apply_window_rules(&window);
+        }
+    }
+}
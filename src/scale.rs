@@ -0,0 +1,118 @@
+use smithay::delegate_fractional_scale;
+use smithay::desktop::{find_popup_root_surface, layer_map_for_output};
+use smithay::output::{Output, Scale};
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::wayland::compositor::{get_parent, send_surface_state, with_states, SurfaceData};
+use smithay::wayland::fractional_scale::{with_fractional_scale, FractionalScaleHandler};
+
+use crate::ipc::Event;
+use crate::state::{BackendDataExt, Compostate};
+
+/// Scales below this make outputs unusably large.
+pub const MIN_SCALE: f64 = 0.25;
+pub const MAX_SCALE: f64 = 8.0;
+
+/// Round `scale` to what `wp_fractional_scale_v1` can express, 120ths.
+pub fn round_scale(scale: f64) -> f64 {
+    (scale.clamp(MIN_SCALE, MAX_SCALE) * 120.0).round() / 120.0
+}
+
+/// Tell a surface about the scale of the output it is shown on: the exact one to clients
+/// supporting fractional scaling, and the next integer up to the others so they are downscaled
+/// rather than blurry.
+pub fn send_scale(surface: &WlSurface, states: &SurfaceData, output: &Output) {
+    let scale = output.current_scale();
+    with_fractional_scale(states, |fractional| {
+        fractional.set_preferred_scale(scale.fractional_scale());
+    });
+    send_surface_state(
+        surface,
+        states,
+        scale.integer_scale(),
+        output.current_transform(),
+    );
+}
+
+impl<BackendData: BackendDataExt + 'static> FractionalScaleHandler for Compostate<BackendData> {
+    fn new_fractional_scale(&mut self, surface: WlSurface) {
+        if let Some(output) = self.surface_output(&surface) {
+            with_states(&surface, |states| send_scale(&surface, states, &output));
+        }
+    }
+}
+
+delegate_fractional_scale!(@<BackendData: BackendDataExt + 'static> Compostate<BackendData>);
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    /// The output showing the window, layer or popup `surface` belongs to, or the one it will
+    /// most likely show up on when it is not mapped yet.
+    pub fn surface_output(&self, surface: &WlSurface) -> Option<Output> {
+        let mut root = match self.popups.find_popup(surface) {
+            Some(popup) => find_popup_root_surface(&popup).ok()?,
+            None => surface.clone(),
+        };
+        while let Some(parent) = get_parent(&root) {
+            root = parent;
+        }
+
+        if let Some(window) = self.window_for_surface(&root) {
+            return self.window_output(&window);
+        }
+        self.layer_output(&root).or_else(|| self.active_output())
+    }
+
+    /// Send the scale of `output` to every surface of the windows and layers on it, popups and
+    /// subsurfaces included. Surfaces are only told about actual changes.
+    pub fn update_surface_scales(&self, output: &Output) {
+        let send = |surface: &WlSurface, states: &SurfaceData| send_scale(surface, states, output);
+        for window in self.space.elements_for_output(output) {
+            window.0.with_surfaces(send);
+        }
+        for layer in layer_map_for_output(output).layers() {
+            layer.with_surfaces(send);
+        }
+    }
+
+    /// Apply the scale the config sets for `output`, if any.
+    pub fn apply_output_scale(&mut self, output: &Output) {
+        let scale = self
+            .config
+            .with_data(|data| data.output_scales.get(&output.name()).copied());
+        if let Some(scale) = scale {
+            self.set_output_scale(output, scale);
+        }
+    }
+
+    pub fn set_output_scale(&mut self, output: &Output, scale: f64) {
+        let scale = round_scale(scale);
+        if output.current_scale().fractional_scale() == scale {
+            return;
+        }
+        output.change_current_state(None, None, Some(Scale::Fractional(scale)), None);
+        println!("output: {} scaled to {scale}", output.name());
+        self.emit(Event::output_mode(output));
+
+        // the logical size of the output changed, which moves every output right of it
+        self.relayout_outputs();
+        self.arrange_all();
+    }
+
+    /// Place the outputs left to right, in their current order, without gaps or overlaps.
+    fn relayout_outputs(&mut self) {
+        let mut outputs = self
+            .space
+            .outputs()
+            .filter_map(|output| Some((output.clone(), self.space.output_geometry(output)?)))
+            .collect::<Vec<_>>();
+        outputs.sort_by_key(|(_, geometry)| geometry.loc.x);
+
+        let mut x = 0;
+        for (output, geometry) in outputs {
+            if geometry.loc != (x, 0).into() {
+                output.change_current_state(None, None, None, Some((x, 0).into()));
+                self.space.map_output(&output, (x, 0));
+            }
+            x += geometry.size.w;
+        }
+    }
+}
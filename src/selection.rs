@@ -0,0 +1,107 @@
+use smithay::input::Seat;
+use smithay::reexports::wayland_server::backend::ClientId;
+use smithay::reexports::wayland_server::protocol::{
+    wl_data_source::WlDataSource, wl_surface::WlSurface,
+};
+use smithay::reexports::wayland_server::Resource;
+use smithay::utils::{Logical, Point};
+use smithay::wayland::seat::WaylandFocus;
+use smithay::wayland::selection::data_device::{
+    clear_data_device_selection, ClientDndGrabHandler, DataDeviceHandler, DataDeviceState,
+    ServerDndGrabHandler,
+};
+use smithay::wayland::selection::primary_selection::{
+    clear_primary_selection, PrimarySelectionHandler, PrimarySelectionState,
+};
+use smithay::wayland::selection::wlr_data_control::{DataControlHandler, DataControlState};
+use smithay::wayland::selection::{SelectionHandler, SelectionSource, SelectionTarget};
+use smithay::{delegate_data_control, delegate_data_device, delegate_primary_selection};
+
+use crate::state::{BackendDataExt, Compostate};
+
+impl<BackendData: BackendDataExt + 'static> SelectionHandler for Compostate<BackendData> {
+    type SelectionUserData = ();
+
+    fn new_selection(
+        &mut self,
+        ty: SelectionTarget,
+        source: Option<SelectionSource>,
+        _seat: Seat<Self>,
+    ) {
+        // a source does not tell who offered it, but clients only get to set a selection while
+        // they have the keyboard. Clipboard managers setting one through data control are the
+        // exception, and simply offer it again once it is cleared under them.
+        let owner = source.and_then(|_| self.focused_client());
+        match ty {
+            SelectionTarget::Clipboard => self.clipboard_owner = owner,
+            SelectionTarget::Primary => self.primary_owner = owner,
+        }
+    }
+}
+
+impl<BackendData: BackendDataExt + 'static> DataDeviceHandler for Compostate<BackendData> {
+    fn data_device_state(&self) -> &DataDeviceState {
+        &self.data_device_state
+    }
+}
+
+impl<BackendData: BackendDataExt + 'static> ClientDndGrabHandler for Compostate<BackendData> {
+    fn started(
+        &mut self,
+        _source: Option<WlDataSource>,
+        icon: Option<WlSurface>,
+        _seat: Seat<Self>,
+    ) {
+        self.dnd_icon = icon;
+    }
+
+    fn dropped(&mut self, _seat: Seat<Self>) {
+        self.dnd_icon = None;
+    }
+}
+
+impl<BackendData: BackendDataExt + 'static> ServerDndGrabHandler for Compostate<BackendData> {}
+
+delegate_data_device!(@<BackendData: BackendDataExt + 'static> Compostate<BackendData>);
+
+impl<BackendData: BackendDataExt + 'static> PrimarySelectionHandler for Compostate<BackendData> {
+    fn primary_selection_state(&self) -> &PrimarySelectionState {
+        &self.primary_selection_state
+    }
+}
+
+delegate_primary_selection!(@<BackendData: BackendDataExt + 'static> Compostate<BackendData>);
+
+impl<BackendData: BackendDataExt + 'static> DataControlHandler for Compostate<BackendData> {
+    fn data_control_state(&self) -> &DataControlState {
+        &self.data_control_state
+    }
+}
+
+delegate_data_control!(@<BackendData: BackendDataExt + 'static> Compostate<BackendData>);
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    /// The icon of the drag and drop operation in progress, and where it goes.
+    pub fn drag_icon(&self) -> Option<(WlSurface, Point<i32, Logical>)> {
+        let icon = self.dnd_icon.as_ref().filter(|icon| icon.is_alive())?;
+        let location = self.seat.get_pointer()?.current_location();
+        Some((icon.clone(), location.to_i32_round()))
+    }
+
+    fn focused_client(&self) -> Option<ClientId> {
+        let surface = self.seat.get_keyboard()?.current_focus()?.wl_surface()?;
+        Some(self.display_handle.get_client(surface.id()).ok()?.id())
+    }
+
+    /// Take the selections offered by `client` away, as nobody can paste them once it is gone.
+    pub fn clear_selections_of(&mut self, client: &ClientId) {
+        if self.clipboard_owner.as_ref() == Some(client) {
+            self.clipboard_owner = None;
+            clear_data_device_selection(&self.display_handle, &self.seat);
+        }
+        if self.primary_owner.as_ref() == Some(client) {
+            self.primary_owner = None;
+            clear_primary_selection(&self.display_handle, &self.seat);
+        }
+    }
+}
@@ -1,15 +1,26 @@
 use anyhow::Result;
+use smithay::backend::renderer::element::memory::MemoryRenderBuffer;
 use smithay::backend::renderer::utils::on_commit_buffer_handler;
-use smithay::desktop::{Space, Window};
-use smithay::input::{SeatHandler, SeatState};
-use smithay::reexports::calloop::{generic::Generic, Interest, LoopHandle, Mode, PostAction};
-use smithay::reexports::wayland_server::{backend::ClientData, Display, DisplayHandle, Resource};
+use smithay::desktop::{PopupKind, PopupManager, Space, Window};
+use smithay::input::keyboard::XkbConfig;
+use smithay::input::pointer::CursorImageStatus;
+use smithay::input::{Seat, SeatHandler, SeatState};
+use smithay::reexports::calloop::channel::{self, Sender};
+use smithay::reexports::calloop::{
+    generic::Generic, Interest, LoopHandle, Mode, PostAction, RegistrationToken,
+};
+use smithay::reexports::wayland_server::backend::{ClientData, ClientId};
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::reexports::wayland_server::{Display, DisplayHandle, Resource};
+use smithay::utils::{Clock, Monotonic};
 use smithay::wayland::buffer::BufferHandler;
 use smithay::wayland::compositor::{
-    add_blocker, add_pre_commit_hook, with_states, BufferAssignment, SurfaceAttributes,
+    add_blocker, add_pre_commit_hook, get_parent, is_sync_subsurface, with_states,
+    BufferAssignment, SurfaceAttributes,
 };
 use smithay::wayland::dmabuf::get_dmabuf;
 use smithay::wayland::keyboard_shortcuts_inhibit::KeyboardShortcutsInhibitHandler;
+use smithay::wayland::seat::WaylandFocus;
 use smithay::wayland::shell::xdg::decoration::XdgDecorationHandler;
 use smithay::wayland::shell::xdg::XdgShellHandler;
 use smithay::wayland::socket::ListeningSocketSource;
@@ -20,12 +31,10 @@ use smithay::wayland::{
     keyboard_shortcuts_inhibit::KeyboardShortcutsInhibitState,
     output::{OutputHandler, OutputManagerState},
     presentation::PresentationState,
-    selection::data_device::DataDeviceHandler,
     selection::{
-        data_device::{ClientDndGrabHandler, DataDeviceState, ServerDndGrabHandler},
-        primary_selection::PrimarySelectionState,
+        data_device::{set_data_device_focus, DataDeviceState},
+        primary_selection::{set_primary_focus, PrimarySelectionState},
         wlr_data_control::DataControlState,
-        SelectionHandler,
     },
     shell::{
         wlr_layer::WlrLayerShellState,
@@ -36,16 +45,27 @@ use smithay::wayland::{
     xdg_activation::XdgActivationState,
 };
 use smithay::{
-    delegate_compositor, delegate_data_device, delegate_keyboard_shortcuts_inhibit,
-    delegate_output, delegate_seat, delegate_shm, delegate_viewporter, delegate_xdg_activation,
-    delegate_xdg_decoration, delegate_xdg_shell,
+    delegate_compositor, delegate_keyboard_shortcuts_inhibit, delegate_output,
+    delegate_presentation, delegate_seat, delegate_shm, delegate_viewporter,
+    delegate_xdg_activation, delegate_xdg_decoration, delegate_xdg_shell,
 };
 
+use crate::config::Config;
+use crate::cursor::default_cursor;
+use crate::decorations::DecorationMode;
 use crate::focus::*;
-use crate::windowdata::CompostoWindow;
-
+use crate::hooks::Hook;
+use crate::ipc::{Event, IpcState};
+use crate::keybindings::KeybindingState;
+use crate::keyboard::keyboard_config;
+use crate::layout::{Layout, TagLayout};
+use crate::tags::MAX_TAGS;
+use crate::windowdata::{initial_configure_sent, CompostoWindow};
+
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
 use std::sync::atomic::AtomicBool;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 pub struct LoopData<BackendData: BackendDataExt + 'static> {
     pub state: Compostate<BackendData>,
@@ -54,11 +74,26 @@ pub struct LoopData<BackendData: BackendDataExt + 'static> {
 
 pub trait BackendDataExt {
     fn seat_name(&self) -> Option<String>;
+    /// Switch to the virtual terminal `vt`, on backends that run on one.
+    fn change_vt(&mut self, vt: i32);
+    /// Something on screen may have changed, render the outputs again once they can take a new
+    /// frame.
+    fn schedule_render(&mut self);
 }
 
-#[derive(Debug, Default)]
 pub struct ClientState {
     compositor_state: CompositorClientState,
+    /// Tells the compositor about the disconnect, which it cannot be reached for from here.
+    disconnects: Mutex<Sender<ClientId>>,
+}
+
+impl ClientState {
+    fn new(disconnects: Sender<ClientId>) -> Self {
+        Self {
+            compositor_state: CompositorClientState::default(),
+            disconnects: Mutex::new(disconnects),
+        }
+    }
 }
 
 impl ClientData for ClientState {
@@ -68,10 +103,12 @@ impl ClientData for ClientState {
 
     fn disconnected(
         &self,
-        _client_id: smithay::reexports::wayland_server::backend::ClientId,
+        client_id: smithay::reexports::wayland_server::backend::ClientId,
         _reason: smithay::reexports::wayland_server::backend::DisconnectReason,
     ) {
         println!("disconnected clinet");
+        // the compositor is going away too when the channel is closed
+        let _ = self.disconnects.lock().unwrap().send(client_id);
     }
 
     fn debug(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -85,14 +122,35 @@ pub struct Compostate<BackendData: BackendDataExt + 'static> {
     pub display_handle: DisplayHandle,
     pub loop_handle: LoopHandle<'static, LoopData<BackendData>>,
     pub space: Space<CompostoWindow>,
+    /// Every toplevel, including the ones unmapped because none of their tags are visible.
+    pub windows: Vec<CompostoWindow>,
+    pub popups: PopupManager,
+    /// Surface dragged along with the pointer during drag and drop.
+    pub dnd_icon: Option<WlSurface>,
+    /// What the client under the pointer wants it to look like.
+    pub cursor_status: CursorImageStatus,
+    /// Drawn for the pointer when the client leaves it to us.
+    pub default_cursor: MemoryRenderBuffer,
+    /// Clients that set the clipboard and primary selections, which go away with them.
+    pub clipboard_owner: Option<ClientId>,
+    pub primary_owner: Option<ClientId>,
+    /// Process ids of the connected clients, for the `client_disconnected` hook.
+    pub client_pids: HashMap<ClientId, i32>,
+    pub tag_layouts: Vec<TagLayout>,
+    pub config: Config,
+    pub seat: Seat<Self>,
+    pub keybindings: KeybindingState,
+    pub ipc: Option<IpcState>,
+    /// Watches the config file while auto-reload is on.
+    pub config_watcher: Option<RegistrationToken>,
 
     // wayland state
     pub compositor_state: CompositorState,
-    // pub data_device_state: DataDeviceState,
-    // pub layer_shell_state: WlrLayerShellState,
+    pub data_device_state: DataDeviceState,
+    pub layer_shell_state: WlrLayerShellState,
     pub output_manager_state: OutputManagerState,
-    // pub primary_selection_state: PrimarySelectionState,
-    // pub data_control_state: DataControlState,
+    pub primary_selection_state: PrimarySelectionState,
+    pub data_control_state: DataControlState,
     // pub keyboard_shortcuts_inhibit_state: KeyboardShortcutsInhibitState,
     //
     pub seat_state: SeatState<Self>,
@@ -101,10 +159,12 @@ pub struct Compostate<BackendData: BackendDataExt + 'static> {
     pub xdg_activation_state: XdgActivationState,
     pub xdg_decoration_state: XdgDecorationState,
     pub xdg_shell_state: XdgShellState,
-    // pub presentation_state: PresentationState,
-    // pub fractional_scale_manager_state: FractionalScaleManagerState,
+    pub presentation_state: PresentationState,
+    pub fractional_scale_manager_state: FractionalScaleManagerState,
     // input: TODO
     //
+    /// Timestamps handed to clients with their frame callbacks and presentation feedback.
+    pub clock: Clock<Monotonic>,
     pub running: AtomicBool,
 }
 
@@ -113,21 +173,39 @@ impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
         display: Display<Compostate<BackendData>>,
         lh: LoopHandle<'static, LoopData<BackendData>>,
         data: BackendData,
+        config: Config,
     ) -> Result<Self> {
         let dh = display.handle();
 
+        let (disconnects, disconnected) = channel::channel();
+        lh.insert_source(disconnected, |event, _, data| {
+            if let channel::Event::Msg(client) = event {
+                data.state.client_disconnected(client);
+            }
+        })?;
+
         let source = ListeningSocketSource::new_auto().unwrap();
         let sock_name = source.socket_name().to_string_lossy().into_owned();
-        lh.insert_source(source, |client, _, data| {
-            if let Err(e) = data
-                .dh
-                .insert_client(client, Arc::new(ClientState::default()))
-            {
-                eprintln!("Unable to add wayland client: {e}");
+        lh.insert_source(source, move |client, _, data| {
+            let client_state = Arc::new(ClientState::new(disconnects.clone()));
+            match data.dh.insert_client(client, client_state) {
+                Ok(client) => {
+                    if let Ok(credentials) = client.get_credentials(&data.dh) {
+                        data.state.client_pids.insert(client.id(), credentials.pid);
+                    }
+                }
+                Err(e) => eprintln!("Unable to add wayland client: {e}"),
             }
         })?;
 
         println!("listening on socket: {sock_name}");
+        // everything we spawn from here on should connect to us
+        std::env::set_var("WAYLAND_DISPLAY", &sock_name);
+
+        // the compositor is perfectly usable without remote control
+        let ipc = IpcState::bind(&lh, &sock_name)
+            .map_err(|e| eprintln!("ipc: unable to create the control socket: {e}"))
+            .ok();
 
         lh.insert_source(
             Generic::new(display, Interest::READ, Mode::Level),
@@ -138,28 +216,110 @@ impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
         )
         .expect("wayland source initialization failed");
 
-        Ok(Self {
+        let mut seat_state = SeatState::new();
+        let seat_name = data.seat_name().unwrap_or_else(|| "seat0".into());
+        let mut seat = seat_state.new_wl_seat(&dh, seat_name);
+        let keyboard = config.with_data(|data| keyboard_config(&data.keyboards, seat.name()));
+        if let Err(e) = seat.add_keyboard(
+            keyboard.xkb_config(),
+            keyboard.repeat_delay,
+            keyboard.repeat_rate,
+        ) {
+            // a typo in the layout must not keep the compositor from starting
+            eprintln!("keyboard: invalid xkb settings, using the default keymap: {e}");
+            seat.add_keyboard(
+                XkbConfig::default(),
+                keyboard.repeat_delay,
+                keyboard.repeat_rate,
+            )?;
+        }
+        seat.add_pointer();
+
+        let primary_selection_state = PrimarySelectionState::new::<Self>(&dh);
+        // clipboard managers get to see and set both selections
+        let data_control_state =
+            DataControlState::new::<Self, _>(&dh, Some(&primary_selection_state), |_| true);
+
+        let clock = Clock::new();
+
+        let mut state = Self {
             backend_data: data,
             display_handle: dh.clone(),
             loop_handle: lh,
             space: Space::default(),
+            windows: Vec::new(),
+            popups: PopupManager::default(),
+            dnd_icon: None,
+            cursor_status: CursorImageStatus::default_named(),
+            default_cursor: default_cursor(),
+            clipboard_owner: None,
+            primary_owner: None,
+            client_pids: HashMap::new(),
+            tag_layouts: vec![TagLayout::new(Layout::MasterStack); MAX_TAGS],
+            config,
+            seat,
+            keybindings: KeybindingState::default(),
+            ipc,
+            config_watcher: None,
             compositor_state: CompositorState::new::<Self>(&dh),
-            // data_device_state: DataDeviceState::new::<Self>(&dh),
-            // layer_shell_state: WlrLayerShellState::new::<Self>(&dh),
+            data_device_state: DataDeviceState::new::<Self>(&dh),
+            layer_shell_state: WlrLayerShellState::new::<Self>(&dh),
             output_manager_state: OutputManagerState::new_with_xdg_output::<Self>(&dh),
-            // primary_selection_state: todo!(),
-            // data_control_state: todo!(),
+            primary_selection_state,
+            data_control_state,
             // keyboard_shortcuts_inhibit_state: KeyboardShortcutsInhibitState::new::<Self>(&dh),
-            seat_state: SeatState::new(),
+            seat_state,
             shm_state: ShmState::new::<Self>(&dh, Vec::new()),
             viewporter_state: ViewporterState::new::<Self>(&dh),
             xdg_activation_state: XdgActivationState::new::<Self>(&dh),
             xdg_decoration_state: XdgDecorationState::new::<Self>(&dh),
             xdg_shell_state: XdgShellState::new::<Self>(&dh),
-            // presentation_state: todo!(),
-            // fractional_scale_manager_state: todo!(),
+            presentation_state: PresentationState::new::<Self>(&dh, clock.id() as u32),
+            fractional_scale_manager_state: FractionalScaleManagerState::new::<Self>(&dh),
+            clock,
             running: AtomicBool::new(true),
-        })
+        };
+
+        state.update_config_watcher();
+        for cmd in state.config.with_data(|data| data.autostart.clone()) {
+            state.spawn(&cmd);
+        }
+        // whatever the config asked for while it was loading
+        state.apply_queued_actions();
+
+        Ok(state)
+    }
+
+    /// Housekeeping after every dispatch of the event loop.
+    pub fn refresh(&mut self) {
+        self.space.refresh();
+        self.popups.cleanup();
+    }
+
+    pub fn schedule_render(&mut self) {
+        self.backend_data.schedule_render();
+    }
+
+    /// Run `cmd` through `sh -c`, detached from the compositor's stdio.
+    pub fn spawn(&self, cmd: &str) {
+        // the shell in between puts `cmd` in the background and exits right away, leaving it to
+        // init to reap instead of turning into a zombie of ours
+        let res = Command::new("/bin/sh")
+            .arg("-c")
+            .arg("/bin/sh -c \"$1\" &")
+            .arg("sh")
+            .arg(cmd)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .and_then(|mut child| child.wait());
+
+        match res {
+            Ok(status) if !status.success() => eprintln!("unable to spawn `{cmd}`: {status}"),
+            Ok(_) => {}
+            Err(e) => eprintln!("unable to spawn `{cmd}`: {e}"),
+        }
     }
 }
 
@@ -183,6 +343,25 @@ impl<BackendData: BackendDataExt + 'static> CompositorHandler for Compostate<Bac
         surface: &smithay::reexports::wayland_server::protocol::wl_surface::WlSurface,
     ) {
         on_commit_buffer_handler::<Self>(surface);
+
+        if !is_sync_subsurface(surface) {
+            let mut root = surface.clone();
+            while let Some(parent) = get_parent(&root) {
+                root = parent;
+            }
+            if let Some(window) = self.window_for_surface(&root) {
+                window.0.on_commit();
+            }
+        }
+
+        if let Some(window) = self.window_for_surface(surface) {
+            self.window_commit(&window);
+            self.resize_commit(&window);
+        }
+
+        self.popup_commit(surface);
+        self.layer_commit(surface);
+        self.schedule_render();
     }
 
     fn new_surface(
@@ -223,9 +402,9 @@ delegate_compositor!(@<BackendData: BackendDataExt + 'static> Compostate<Backend
 impl<BackendData: BackendDataExt + 'static> BufferHandler for Compostate<BackendData> {
     fn buffer_destroyed(
         &mut self,
-        buffer: &smithay::reexports::wayland_server::protocol::wl_buffer::WlBuffer,
+        _buffer: &smithay::reexports::wayland_server::protocol::wl_buffer::WlBuffer,
     ) {
-        todo!()
+        // nothing is kept per buffer, the renderers drop their textures with the surface state
     }
 }
 
@@ -239,6 +418,8 @@ delegate_shm!(@<BackendData: BackendDataExt + 'static> Compostate<BackendData>);
 
 delegate_viewporter!(@<BackendData: BackendDataExt + 'static> Compostate<BackendData>);
 
+delegate_presentation!(@<BackendData: BackendDataExt + 'static> Compostate<BackendData>);
+
 impl<BackendData: BackendDataExt + 'static> XdgActivationHandler for Compostate<BackendData> {
     fn activation_state(&mut self) -> &mut XdgActivationState {
         &mut self.xdg_activation_state
@@ -250,8 +431,7 @@ impl<BackendData: BackendDataExt + 'static> XdgActivationHandler for Compostate<
         token_data: smithay::wayland::xdg_activation::XdgActivationTokenData,
         surface: smithay::reexports::wayland_server::protocol::wl_surface::WlSurface,
     ) {
-        // move the window to the top of the space stack?
-        unimplemented!()
+        self.activate(token, token_data, surface);
     }
 }
 
@@ -259,19 +439,19 @@ delegate_xdg_activation!(@<BackendData: BackendDataExt + 'static> Compostate<Bac
 
 impl<BackendData: BackendDataExt + 'static> XdgDecorationHandler for Compostate<BackendData> {
     fn new_decoration(&mut self, toplevel: smithay::wayland::shell::xdg::ToplevelSurface) {
-        todo!()
+        self.set_decoration_mode(&toplevel, DecorationMode::ServerSide);
     }
 
     fn request_mode(
         &mut self,
         toplevel: smithay::wayland::shell::xdg::ToplevelSurface,
-        mode: smithay::reexports::wayland_protocols::xdg::decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode,
+        mode: DecorationMode,
     ) {
-        todo!()
+        self.set_decoration_mode(&toplevel, mode);
     }
 
     fn unset_mode(&mut self, toplevel: smithay::wayland::shell::xdg::ToplevelSurface) {
-        todo!()
+        self.set_decoration_mode(&toplevel, DecorationMode::ServerSide);
     }
 }
 
@@ -283,17 +463,59 @@ impl<BackendData: BackendDataExt + 'static> XdgShellHandler for Compostate<Backe
     }
 
     fn new_toplevel(&mut self, surface: smithay::wayland::shell::xdg::ToplevelSurface) {
+        // the layout decides the size, the configure goes out on the initial commit
         let window = CompostoWindow(Window::new_wayland_window(surface));
+        self.add_window(window);
+    }
 
-        if let Some(toplevel) = window.0.toplevel() {
-            toplevel.with_pending_state(|state| {
-                state.size = Some((800.into(), 600.into()).into());
-                state.bounds = Some((800.into(), 800.into()).into());
-            });
-            toplevel.send_pending_configure();
-        }
+    fn move_request(
+        &mut self,
+        surface: smithay::wayland::shell::xdg::ToplevelSurface,
+        seat: smithay::reexports::wayland_server::protocol::wl_seat::WlSeat,
+        serial: smithay::utils::Serial,
+    ) {
+        self.client_move_request(&surface, &seat, serial);
+    }
+
+    fn resize_request(
+        &mut self,
+        surface: smithay::wayland::shell::xdg::ToplevelSurface,
+        seat: smithay::reexports::wayland_server::protocol::wl_seat::WlSeat,
+        serial: smithay::utils::Serial,
+        edges: smithay::reexports::wayland_protocols::xdg::shell::server::xdg_toplevel::ResizeEdge,
+    ) {
+        self.client_resize_request(&surface, &seat, serial, edges);
+    }
+
+    fn fullscreen_request(
+        &mut self,
+        surface: smithay::wayland::shell::xdg::ToplevelSurface,
+        output: Option<smithay::reexports::wayland_server::protocol::wl_output::WlOutput>,
+    ) {
+        self.client_fullscreen_request(&surface, true, output);
+    }
+
+    fn unfullscreen_request(&mut self, surface: smithay::wayland::shell::xdg::ToplevelSurface) {
+        self.client_fullscreen_request(&surface, false, None);
+    }
+
+    fn maximize_request(&mut self, surface: smithay::wayland::shell::xdg::ToplevelSurface) {
+        self.client_maximize_request(&surface, true);
+    }
 
-        self.space.map_element(window.clone(), (10, 100), true);
+    fn unmaximize_request(&mut self, surface: smithay::wayland::shell::xdg::ToplevelSurface) {
+        self.client_maximize_request(&surface, false);
+    }
+
+    fn toplevel_destroyed(&mut self, surface: smithay::wayland::shell::xdg::ToplevelSurface) {
+        let window = self
+            .windows
+            .iter()
+            .find(|window| window.0.toplevel() == Some(&surface))
+            .cloned();
+        if let Some(window) = window {
+            self.remove_window(&window);
+        }
     }
 
     fn new_popup(
@@ -301,7 +523,14 @@ impl<BackendData: BackendDataExt + 'static> XdgShellHandler for Compostate<Backe
         surface: smithay::wayland::shell::xdg::PopupSurface,
         positioner: smithay::wayland::shell::xdg::PositionerState,
     ) {
-        todo!()
+        surface.with_pending_state(|state| {
+            state.geometry = positioner.get_geometry();
+            state.positioner = positioner;
+        });
+        self.unconstrain_popup(&surface);
+        if let Err(e) = self.popups.track_popup(PopupKind::Xdg(surface)) {
+            eprintln!("popup: unable to track popup: {e}");
+        }
     }
 
     fn grab(
@@ -310,7 +539,7 @@ impl<BackendData: BackendDataExt + 'static> XdgShellHandler for Compostate<Backe
         seat: smithay::reexports::wayland_server::protocol::wl_seat::WlSeat,
         serial: smithay::utils::Serial,
     ) {
-        todo!()
+        self.grab_popup(surface, seat, serial);
     }
 
     fn reposition_request(
@@ -319,7 +548,12 @@ impl<BackendData: BackendDataExt + 'static> XdgShellHandler for Compostate<Backe
         positioner: smithay::wayland::shell::xdg::PositionerState,
         token: u32,
     ) {
-        todo!()
+        surface.with_pending_state(|state| {
+            state.geometry = positioner.get_geometry();
+            state.positioner = positioner;
+        });
+        self.unconstrain_popup(&surface);
+        surface.send_repositioned(token);
     }
 }
 
@@ -335,6 +569,36 @@ impl<BackendData: BackendDataExt + 'static> SeatHandler for Compostate<BackendDa
     fn seat_state(&mut self) -> &mut SeatState<Self> {
         &mut self.seat_state
     }
+
+    fn cursor_image(&mut self, _seat: &Seat<Self>, image: CursorImageStatus) {
+        self.cursor_status = image;
+        self.schedule_render();
+    }
+
+    fn focus_changed(&mut self, seat: &Seat<Self>, focused: Option<&CompostorFocus>) {
+        // selections are offered to whoever has the keyboard
+        let client = focused
+            .and_then(|focus| focus.wl_surface())
+            .and_then(|surface| self.display_handle.get_client(surface.id()).ok());
+        set_data_device_focus(&self.display_handle, seat, client.clone());
+        set_primary_focus(&self.display_handle, seat, client);
+
+        // getting the focus is all the attention a window can ask for
+        if let Some(CompostorFocus::Window(window)) = focused {
+            window.state().urgent = false;
+        }
+        // the keyboard is locked while it tells us about the new focus
+        self.update_decorations_for(focused);
+
+        let id = match focused {
+            Some(CompostorFocus::Window(window)) => Some(window.state().id),
+            _ => None,
+        };
+        self.emit(Event::WindowFocused { id });
+        if let Some(CompostorFocus::Window(window)) = focused {
+            self.window_hook(Hook::WindowFocus, window);
+        }
+    }
 }
 
 delegate_seat!(@<BackendData: BackendDataExt + 'static> Compostate<BackendData>);
@@ -0,0 +1,338 @@
+use std::cell::Cell;
+
+use smithay::backend::renderer::utils::with_renderer_surface_state;
+use smithay::output::Output;
+use smithay::reexports::wayland_server::protocol::wl_surface::WlSurface;
+use smithay::utils::{Point, Serial, SERIAL_COUNTER};
+
+use crate::focus::CompostorFocus;
+use crate::hooks::Hook;
+use crate::ipc::{tag_indices, Event};
+use crate::rules::min_size_hint;
+use crate::state::{BackendDataExt, Compostate};
+use crate::windowdata::{initial_configure_sent, next_window_id, CompostoWindow};
+
+/// Maximum number of tags, one per bit of the masks.
+pub const MAX_TAGS: usize = 32;
+
+struct VisibleTags(Cell<u32>);
+
+/// Bitmask of the tags currently shown on `output`. Outputs start out showing the first tag.
+pub fn visible_tags(output: &Output) -> u32 {
+    output
+        .user_data()
+        .insert_if_missing(|| VisibleTags(Cell::new(1)));
+    output.user_data().get::<VisibleTags>().unwrap().0.get()
+}
+
+fn set_visible_tags(output: &Output, tags: u32) {
+    visible_tags(output);
+    output.user_data().get::<VisibleTags>().unwrap().0.set(tags);
+}
+
+impl<BackendData: BackendDataExt + 'static> Compostate<BackendData> {
+    /// The output new windows go to and tag commands without an explicit output apply to.
+    pub fn active_output(&self) -> Option<Output> {
+        self.space.outputs().next().cloned()
+    }
+
+    pub fn output_by_name(&self, name: &str) -> Option<Output> {
+        self.space
+            .outputs()
+            .find(|output| output.name() == name)
+            .cloned()
+    }
+
+    /// The window with the keyboard, or else the topmost one that can take it.
+    pub fn focused_window(&self) -> Option<CompostoWindow> {
+        let focus = self
+            .seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus());
+        if let Some(CompostorFocus::Window(window)) = focus {
+            if self.windows.contains(&window) && self.space.element_location(&window).is_some() {
+                return Some(window);
+            }
+        }
+        self.space
+            .elements()
+            .rev()
+            .find(|window| !window.state().never_focus)
+            .cloned()
+    }
+
+    /// Raise `window`, mark it as the activated one and give it the keyboard. Windows a rule keeps
+    /// from being focused are only raised.
+    pub fn focus_window(&mut self, window: &CompostoWindow, serial: Serial) {
+        if window.state().never_focus {
+            self.space.raise_element(window, false);
+            self.restack();
+            self.schedule_render();
+            return;
+        }
+        self.space.raise_element(window, true);
+        // raising never takes a window above those of a higher placement
+        self.restack();
+        for window in &self.windows {
+            if let Some(toplevel) = window.0.toplevel() {
+                if initial_configure_sent(toplevel) {
+                    toplevel.send_pending_configure();
+                }
+            }
+        }
+        if let Some(keyboard) = self.seat.get_keyboard() {
+            keyboard.set_focus(self, Some(CompostorFocus::Window(window.clone())), serial);
+        }
+    }
+
+    /// The output a window lives on. Windows whose output went away are moved to the active one.
+    pub fn window_output(&self, window: &CompostoWindow) -> Option<Output> {
+        let name = window.state().output.clone();
+        name.and_then(|name| self.output_by_name(&name))
+            .or_else(|| self.active_output())
+    }
+
+    pub fn is_window_visible(&self, window: &CompostoWindow) -> bool {
+        match self.window_output(window) {
+            Some(output) => window.state().tags & visible_tags(&output) != 0,
+            None => false,
+        }
+    }
+
+    /// Put a new window on the active output, showing it on the tags currently visible there.
+    pub fn add_window(&mut self, window: CompostoWindow) {
+        let output = self.active_output();
+        {
+            let mut state = window.state();
+            state.id = next_window_id();
+            state.tags = output.as_ref().map(visible_tags).unwrap_or(1);
+            state.output = output.as_ref().map(|output| output.name());
+            state.location = output
+                .as_ref()
+                .and_then(|output| self.space.output_geometry(output))
+                .map(|geometry| geometry.loc)
+                .unwrap_or_default()
+                + Point::from((10, 100));
+        }
+        self.windows.push(window);
+        self.update_visibility();
+    }
+
+    /// Handle a commit of the toplevel surface of `window`.
+    pub fn window_commit(&mut self, window: &CompostoWindow) {
+        let Some(toplevel) = window.0.toplevel() else {
+            return;
+        };
+        let (title_changed, app_id_changed) = window.update_identity();
+        let id = window.state().id;
+
+        if !initial_configure_sent(toplevel) {
+            window.state().min_size_hint = min_size_hint(window);
+            self.apply_window_rules(window);
+            toplevel.send_configure();
+            self.window_hook(Hook::WindowNew, window);
+            return;
+        }
+
+        // the window shows up with its first buffer, and goes away when the client takes it away
+        let has_buffer =
+            with_renderer_surface_state(toplevel.wl_surface(), |state| state.buffer().is_some())
+                .unwrap_or(false);
+        let had_buffer = std::mem::replace(&mut window.state().has_buffer, has_buffer);
+        if has_buffer != had_buffer {
+            if has_buffer {
+                self.emit(Event::WindowMapped {
+                    id,
+                    app_id: window.app_id(),
+                    title: window.title(),
+                });
+            } else {
+                self.emit(Event::WindowUnmapped { id });
+            }
+        }
+
+        // plenty of clients only set these after the initial commit
+        if title_changed || app_id_changed {
+            self.apply_window_rules(window);
+        }
+        if title_changed {
+            let title = window.title();
+            self.emit(Event::WindowTitleChanged { id, title });
+            self.window_hook(Hook::TitleChanged, window);
+        }
+        if app_id_changed {
+            let app_id = window.app_id();
+            self.emit(Event::WindowAppIdChanged { id, app_id });
+            self.window_hook(Hook::AppIdChanged, window);
+        }
+    }
+
+    pub fn remove_window(&mut self, window: &CompostoWindow) {
+        let (id, has_buffer) = {
+            let state = window.state();
+            (state.id, state.has_buffer)
+        };
+        if has_buffer {
+            self.emit(Event::WindowUnmapped { id });
+        }
+        self.windows.retain(|w| w != window);
+        self.space.unmap_elem(window);
+        self.arrange_all();
+
+        // hand the keyboard on instead of leaving it with a window that is gone
+        if let Some(keyboard) = self.seat.get_keyboard() {
+            if keyboard.current_focus() == Some(CompostorFocus::Window(window.clone())) {
+                let serial = SERIAL_COUNTER.next_serial();
+                match self.focused_window() {
+                    Some(next) => self.focus_window(&next, serial),
+                    None => keyboard.set_focus(self, None, serial),
+                }
+            }
+        }
+
+        self.window_hook(Hook::WindowClose, window);
+    }
+
+    pub fn window_for_surface(&self, surface: &WlSurface) -> Option<CompostoWindow> {
+        self.windows
+            .iter()
+            .find(|window| {
+                window
+                    .0
+                    .toplevel()
+                    .map_or(false, |toplevel| toplevel.wl_surface() == surface)
+            })
+            .cloned()
+    }
+
+    /// Map every window whose tags are visible on its output and unmap all the others.
+    pub fn update_visibility(&mut self) {
+        let focus = self
+            .seat
+            .get_keyboard()
+            .and_then(|keyboard| keyboard.current_focus());
+
+        for window in self.windows.clone() {
+            let visible = self.is_window_visible(&window);
+            let location = self.space.element_location(&window);
+
+            match (visible, location) {
+                (true, None) => {
+                    let location = window.state().location;
+                    self.space.map_element(window, location, true);
+                }
+                (false, Some(location)) => {
+                    window.state().location = location;
+                    self.space.unmap_elem(&window);
+                }
+                _ => {}
+            }
+        }
+
+        self.arrange_all();
+
+        // the keyboard goes on to what is left on the output of a window that was just hidden
+        if let Some(CompostorFocus::Window(window)) = focus {
+            if self.windows.contains(&window) && self.space.element_location(&window).is_none() {
+                let output = self.window_output(&window);
+                self.focus_top_window(output.as_ref());
+            }
+        }
+    }
+
+    /// Give the keyboard to the topmost window shown on `output`, or to nobody if there is none.
+    fn focus_top_window(&mut self, output: Option<&Output>) {
+        let top = self
+            .space
+            .elements()
+            .rev()
+            .find(|window| {
+                !window.state().never_focus && self.window_output(window).as_ref() == output
+            })
+            .cloned();
+        let serial = SERIAL_COUNTER.next_serial();
+        match top {
+            Some(window) => self.focus_window(&window, serial),
+            None => {
+                if let Some(keyboard) = self.seat.get_keyboard() {
+                    keyboard.set_focus(self, None, serial);
+                }
+            }
+        }
+    }
+
+    fn emit_tags_changed(&mut self, output: &Output) {
+        self.emit(Event::TagsChanged {
+            output: output.name(),
+            tags: tag_indices(visible_tags(output)),
+        });
+        self.output_hook(Hook::TagChanged, output);
+    }
+
+    fn tag_output(&self, output: Option<String>) -> Option<Output> {
+        match output {
+            Some(name) => self.output_by_name(&name),
+            None => self.active_output(),
+        }
+    }
+
+    /// Show exactly `tags` on an output.
+    pub fn view_tags(&mut self, tags: u32, output: Option<String>) {
+        if tags == 0 {
+            return;
+        }
+        if let Some(output) = self.tag_output(output) {
+            if visible_tags(&output) == tags {
+                return;
+            }
+            set_visible_tags(&output, tags);
+            self.update_visibility();
+            self.emit_tags_changed(&output);
+        }
+    }
+
+    /// Flip the visibility of `tags` on an output, always leaving at least one tag shown.
+    pub fn toggle_tags(&mut self, tags: u32, output: Option<String>) {
+        if let Some(output) = self.tag_output(output) {
+            let visible = visible_tags(&output) ^ tags;
+            if visible != 0 && tags != 0 {
+                set_visible_tags(&output, visible);
+                self.update_visibility();
+                self.emit_tags_changed(&output);
+            }
+        }
+    }
+
+    /// Move the focused window to exactly `tags`.
+    pub fn move_to_tags(&mut self, tags: u32) {
+        if let Some(window) = self.focused_window() {
+            self.move_window_to_tags(&window, tags);
+        }
+    }
+
+    pub fn move_window_to_tags(&mut self, window: &CompostoWindow, tags: u32) {
+        if tags == 0 {
+            return;
+        }
+        window.state().tags = tags;
+        self.update_visibility();
+    }
+
+    pub fn window_by_id(&self, id: u64) -> Option<CompostoWindow> {
+        self.windows
+            .iter()
+            .find(|window| window.state().id == id)
+            .cloned()
+    }
+
+    /// Add or remove the focused window from `tags`, never leaving it without a tag.
+    pub fn toggle_window_tags(&mut self, tags: u32) {
+        if let Some(window) = self.focused_window() {
+            let new_tags = window.state().tags ^ tags;
+            if new_tags != 0 {
+                window.state().tags = new_tags;
+                self.update_visibility();
+            }
+        }
+    }
+}
@@ -1,22 +1,158 @@
+use std::cell::{RefCell, RefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use smithay::backend::renderer::element::solid::SolidColorRenderElement;
 use smithay::backend::renderer::element::surface::WaylandSurfaceRenderElement;
 use smithay::backend::renderer::{element::AsRenderElements, gles::GlesRenderer};
 use smithay::backend::renderer::{ImportAll, ImportMem, Renderer, Texture};
 use smithay::desktop::{space::SpaceElement, Window};
 use smithay::render_elements;
-use smithay::utils::{IsAlive, Physical, Point, Scale};
+use smithay::utils::{IsAlive, Logical, Physical, Point, Scale, Size};
+use smithay::wayland::compositor::with_states;
+use smithay::wayland::shell::xdg::{
+    ToplevelSurface, XdgToplevelSurfaceData, XdgToplevelSurfaceRoleAttributes,
+};
+
+use crate::decorations::{Decoration, DecorationMode};
+use crate::floating::ResizeState;
+use crate::layout::Placement;
 
-#[derive(PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct CompostoWindow(pub Window);
 
+/// Hand out a new window id. Ids are never reused, so scripts holding on to one cannot end up
+/// talking about another window.
+pub fn next_window_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Compositor-side state of a window, shared between all clones of a [`CompostoWindow`].
+#[derive(Debug, Default)]
+pub struct WindowState {
+    /// Identifies the window over IPC.
+    pub id: u64,
+    /// Bitmask of the tags the window is on.
+    pub tags: u32,
+    /// Name of the output the window belongs to.
+    pub output: Option<String>,
+    /// Where the window was last mapped, so it comes back in the same place.
+    pub location: Point<i32, Logical>,
+    /// Whether the compositor draws the decorations of the window.
+    pub server_side: bool,
+    /// Set when the window asked for attention without getting the focus.
+    pub urgent: bool,
+    /// The frame drawn around a window with server-side decorations.
+    pub decoration: Option<Decoration>,
+    /// Title and app_id as of the last commit, to notice when the client changes them.
+    pub title: Option<String>,
+    pub app_id: Option<String>,
+    /// Minimum size the client set before the window was first configured, for window rules.
+    pub min_size_hint: Size<i32, Logical>,
+    /// Indices of the window rules applied to the window so far.
+    pub applied_rules: Vec<usize>,
+    /// Floating windows are left out of the layout and keep their own location.
+    pub floating: bool,
+    /// Size of the window while floating, or `None` to let the client choose.
+    pub floating_size: Option<Size<i32, Logical>>,
+    /// Opacity the window is drawn with, fully opaque when unset.
+    pub opacity: Option<f32>,
+    /// Decoration mode forced by a window rule, whatever the client asks for.
+    pub decoration_mode: Option<DecorationMode>,
+    /// The window never gets the keyboard.
+    pub never_focus: bool,
+    /// Set while the window is resized with the pointer.
+    pub resize: Option<ResizeState>,
+    /// Maximized and fullscreen windows go back to being tiled or floating once they are not
+    /// anymore.
+    pub maximized: bool,
+    pub fullscreen: bool,
+    /// Whether the client attached a buffer, so subscribers were told the window is mapped.
+    pub has_buffer: bool,
+}
+
+impl WindowState {
+    pub fn placement(&self) -> Placement {
+        if self.fullscreen {
+            Placement::Fullscreen
+        } else if self.maximized {
+            Placement::Maximized
+        } else if self.floating {
+            Placement::Floating
+        } else {
+            Placement::Tiled
+        }
+    }
+}
+
+impl CompostoWindow {
+    pub fn app_id(&self) -> Option<String> {
+        self.with_toplevel_data(|data| data.app_id.clone())
+    }
+
+    pub fn title(&self) -> Option<String> {
+        self.with_toplevel_data(|data| data.title.clone())
+    }
+
+    fn with_toplevel_data<T>(
+        &self,
+        f: impl FnOnce(&XdgToplevelSurfaceRoleAttributes) -> Option<T>,
+    ) -> Option<T> {
+        let toplevel = self.0.toplevel()?;
+        with_states(toplevel.wl_surface(), |states| {
+            f(&states
+                .data_map
+                .get::<XdgToplevelSurfaceData>()?
+                .lock()
+                .unwrap())
+        })
+    }
+
+    /// Remember the current title and app_id, returning whether each of them changed since the
+    /// last call.
+    pub fn update_identity(&self) -> (bool, bool) {
+        let (title, app_id) = (self.title(), self.app_id());
+        let mut state = self.state();
+        let changed = (state.title != title, state.app_id != app_id);
+        state.title = title;
+        state.app_id = app_id;
+        changed
+    }
+
+    pub fn state(&self) -> RefMut<'_, WindowState> {
+        self.0
+            .user_data()
+            .insert_if_missing(|| RefCell::new(WindowState::default()));
+        self.0
+            .user_data()
+            .get::<RefCell<WindowState>>()
+            .unwrap()
+            .borrow_mut()
+    }
+}
+
 impl IsAlive for CompostoWindow {
     fn alive(&self) -> bool {
         self.0.alive()
     }
 }
 
+pub fn initial_configure_sent(toplevel: &ToplevelSurface) -> bool {
+    with_states(toplevel.wl_surface(), |states| {
+        states
+            .data_map
+            .get::<XdgToplevelSurfaceData>()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .initial_configure_sent
+    })
+}
+
 render_elements!(
     pub WindowRenderElement<R> where R: ImportAll + ImportMem;
     Window=WaylandSurfaceRenderElement<R>,
+    Decoration=SolidColorRenderElement,
 );
 
 impl<R> AsRenderElements<R> for CompostoWindow
@@ -33,17 +169,36 @@ where
         scale: Scale<f64>,
         alpha: f32,
     ) -> Vec<C> {
-        // let window_bbox = SpaceElement::bbox(&self.0);
-        AsRenderElements::render_elements(&self.0, renderer, location, scale, alpha)
-            .into_iter()
-            .map(C::from)
-            .collect()
+        let alpha = alpha * self.state().opacity.unwrap_or(1.0);
+        let mut elements: Vec<WindowRenderElement<R>> =
+            AsRenderElements::render_elements(&self.0, renderer, location, scale, alpha);
+        // the frame goes below the window and its popups
+        if let Some(decoration) = &mut self.state().decoration {
+            let geometry = self.0.geometry();
+            let origin = location + geometry.loc.to_physical_precise_round(scale);
+            elements.extend(
+                decoration
+                    .render_elements(geometry.size, origin, scale, alpha)
+                    .into_iter()
+                    .map(WindowRenderElement::Decoration),
+            );
+        }
+        elements.into_iter().map(C::from).collect()
     }
 }
 
 impl SpaceElement for CompostoWindow {
     fn bbox(&self) -> smithay::utils::Rectangle<i32, smithay::utils::Logical> {
-        self.0.bbox()
+        let bbox = self.0.bbox();
+        match &self.state().decoration {
+            Some(decoration) => {
+                let geometry = self.0.geometry();
+                let mut frame = decoration.frame(geometry.size);
+                frame.loc += geometry.loc;
+                bbox.merge(frame)
+            }
+            None => bbox,
+        }
     }
 
     fn is_in_input_region(
@@ -62,10 +217,10 @@ impl SpaceElement for CompostoWindow {
         output: &smithay::output::Output,
         overlap: smithay::utils::Rectangle<i32, smithay::utils::Logical>,
     ) {
-        // self.0.output_enter(output, overlap)
+        self.0.output_enter(output, overlap)
     }
 
     fn output_leave(&self, output: &smithay::output::Output) {
-        todo!()
+        self.0.output_leave(output)
     }
 }